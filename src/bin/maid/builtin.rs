@@ -0,0 +1,143 @@
+use crate::layer;
+use serde_yaml::Value;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The rules shipped with the maid, the layer below every other
+pub const BUILTIN: &str = include_str!("../../../maidsweep.yaml");
+
+/// The built-in rules, as a layer to put the others over
+pub fn layer() -> Value {
+    let mut layer = serde_yaml::from_str(BUILTIN).expect("the built-in rules are valid YAML");
+    layer::normalize(&mut layer);
+    layer
+}
+
+/// Write the built-in rules out, to be edited
+pub fn init(path: &Path, force: bool) -> Result<(), Box<dyn Error>> {
+    if path.exists() && !force {
+        return Err(format!(
+            "{} exists already, give --force to overwrite it",
+            path.display()
+        )
+        .into());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, BUILTIN)?;
+    println!("Wrote the built-in rules to {}", path.display());
+    Ok(())
+}
+
+/// A value on one line, like `[a, b]` or `{name: x, tags: [y]}`
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Null => "~".to_owned(),
+        Value::Bool(value) => value.to_string(),
+        Value::Number(value) => value.to_string(),
+        Value::String(value) => value.clone(),
+        Value::Sequence(values) => {
+            let values: Vec<String> = values.iter().map(inline).collect();
+            format!("[{}]", values.join(", "))
+        }
+        Value::Mapping(mapping) => {
+            let entries: Vec<String> = mapping
+                .iter()
+                .map(|(key, value)| format!("{}: {}", inline(key), inline(value)))
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+    }
+}
+
+/// The item of a list standing for one of another list, the rule of the same name,
+/// or else the same value
+fn counterpart<'a>(value: &Value, others: &'a [Value]) -> Option<&'a Value> {
+    match value.get("name") {
+        Some(name) => others.iter().find(|other| other.get("name") == Some(name)),
+        None => others.iter().find(|other| *other == value),
+    }
+}
+
+/// What is added (`+`), removed (`-`) or changed (`~`) from the built-in rules, by path
+fn differences(path: &str, builtin: &Value, merged: &Value, changes: &mut Vec<String>) {
+    let child = |key: &Value| match path {
+        "" => inline(key),
+        path => format!("{}.{}", path, inline(key)),
+    };
+    match (builtin, merged) {
+        (Value::Mapping(builtin), Value::Mapping(merged)) => {
+            for (key, value) in builtin.iter() {
+                match merged.get(key) {
+                    Some(other) => differences(&child(key), value, other, changes),
+                    None => changes.push(format!("- {}: {}", child(key), inline(value))),
+                }
+            }
+            for (key, value) in merged.iter().filter(|(key, _)| !builtin.contains_key(key)) {
+                changes.push(format!("+ {}: {}", child(key), inline(value)));
+            }
+        }
+        (Value::Sequence(builtin), Value::Sequence(merged)) => {
+            for value in builtin.iter() {
+                match (counterpart(value, merged), value.get("name")) {
+                    (Some(other), Some(name)) => {
+                        let path = format!("{}[{}]", path, inline(name));
+                        differences(&path, value, other, changes);
+                    }
+                    (Some(_), None) => (),
+                    (None, _) => changes.push(format!("- {}: {}", path, inline(value))),
+                }
+            }
+            for value in merged.iter() {
+                if counterpart(value, builtin).is_none() {
+                    changes.push(format!("+ {}: {}", path, inline(value)));
+                }
+            }
+        }
+        (builtin, merged) if builtin != merged => changes.push(format!(
+            "~ {}: {} -> {}",
+            path,
+            inline(builtin),
+            inline(merged)
+        )),
+        _ => (),
+    }
+}
+
+/// Print how the configuration files, put over the built-in rules, differ from them
+pub fn diff(config_paths: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    if config_paths.is_empty() {
+        println!("No configuration file, the built-in rules are used");
+        return Ok(());
+    }
+    let builtin = layer();
+    let mut merged = builtin.clone();
+    let mut seen = HashSet::new();
+    for config_path in config_paths {
+        for (_, layer) in layer::read_layers(config_path, &mut seen)? {
+            merged = layer::merge(merged, layer)?;
+        }
+    }
+
+    let mut changes = vec![];
+    differences("", &builtin, &merged, &mut changes);
+    for change in changes.iter() {
+        println!("{}", change);
+    }
+    let files: Vec<String> = config_paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    match changes.len() {
+        0 => println!("No changes to the built-in rules in {}", files.join(", ")),
+        count => println!(
+            "{} changes to the built-in rules in {}",
+            count,
+            files.join(", ")
+        ),
+    }
+    Ok(())
+}
@@ -0,0 +1,445 @@
+use crate::config::{self, ConfigError, PatternsYamlSchema, TypicalEntry};
+use crate::{builtin, layer, sniff};
+use regex::RegexSet;
+use serde_yaml::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+/// Tags given by the maid itself, not by a rule
+const OWN_TAGS: [&str; 3] = ["misc", "garbage", "duplicate"];
+
+enum Node {
+    /// The key is None until it is read
+    Mapping {
+        path: String,
+        key: Option<String>,
+    },
+    Sequence {
+        path: String,
+        index: usize,
+    },
+}
+
+/// The line of every node in a YAML document, by its path like `typical_files[2].patterns[0]`
+#[derive(Default)]
+struct Lines {
+    stack: Vec<Node>,
+    lines: HashMap<String, usize>,
+}
+
+impl Lines {
+    /// The path of the value coming next
+    fn next_path(&self) -> String {
+        match self.stack.last() {
+            None => String::new(),
+            Some(Node::Mapping {
+                path,
+                key: Some(key),
+            }) if path.is_empty() => key.clone(),
+            Some(Node::Mapping {
+                path,
+                key: Some(key),
+            }) => format!("{}.{}", path, key),
+            Some(Node::Mapping { path, key: None }) => path.clone(),
+            Some(Node::Sequence { path, index }) => format!("{}[{}]", path, index),
+        }
+    }
+
+    /// A value is read, the next one in its mapping or sequence comes
+    fn advance(&mut self) {
+        match self.stack.last_mut() {
+            Some(Node::Mapping { key, .. }) => *key = None,
+            Some(Node::Sequence { index, .. }) => *index += 1,
+            None => (),
+        }
+    }
+
+    /// The line of a path, or of the nearest node holding it
+    fn line_of(&self, path: &str) -> usize {
+        let mut path = path;
+        loop {
+            if let Some(line) = self.lines.get(path) {
+                return *line;
+            }
+            match path.rfind(['.', '[']) {
+                Some(end) => path = &path[..end],
+                None => return 0,
+            }
+        }
+    }
+}
+
+impl MarkedEventReceiver for Lines {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        let line = marker.line();
+        match event {
+            Event::Scalar(value, ..) => {
+                if let Some(Node::Mapping { key: None, .. }) = self.stack.last() {
+                    let path = match self.stack.last() {
+                        Some(Node::Mapping { path, .. }) if !path.is_empty() => {
+                            format!("{}.{}", path, value)
+                        }
+                        _ => value.clone(),
+                    };
+                    self.lines.entry(path).or_insert(line);
+                    if let Some(Node::Mapping { key, .. }) = self.stack.last_mut() {
+                        *key = Some(value);
+                    }
+                } else {
+                    self.lines.entry(self.next_path()).or_insert(line);
+                    self.advance();
+                }
+            }
+            Event::Alias(_) => {
+                self.lines.entry(self.next_path()).or_insert(line);
+                self.advance();
+            }
+            Event::MappingStart(_) => {
+                let path = self.next_path();
+                self.lines.entry(path.clone()).or_insert(line);
+                self.stack.push(Node::Mapping { path, key: None });
+            }
+            Event::SequenceStart(_) => {
+                let path = self.next_path();
+                self.lines.entry(path.clone()).or_insert(line);
+                self.stack.push(Node::Sequence { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.advance();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The one name a pattern matches, if it is a plain name like `^Makefile$` or `^Game\.exe$`
+fn literal_name(pattern: &str) -> Option<String> {
+    let inner = pattern.strip_prefix('^')?.strip_suffix('$')?;
+    let mut name = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                c if c.is_ascii_alphanumeric() => return None,
+                c => name.push(c),
+            },
+            c if "[](){}.*+?|^$".contains(c) => return None,
+            c => name.push(c),
+        }
+    }
+    Some(name)
+}
+
+/// Rules tried first that always win over a pattern of a later rule, for the same marker
+fn shadowed_patterns(entries: Vec<TypicalEntry>) -> Vec<ConfigError> {
+    let mut entries: Vec<(String, TypicalEntry)> = entries
+        .into_iter()
+        .enumerate()
+        .map(|(index, entry)| (entry.name(index), entry))
+        .collect();
+    // the order they are tried in
+    entries.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.rule.priority));
+
+    let mut warnings = vec![];
+    for (later, (name, entry)) in entries.iter().enumerate() {
+        for (index, pattern) in entry.rule.patterns.iter().enumerate() {
+            let literal = literal_name(pattern);
+            // a rule needing more files does not always win
+            let shadowing = entries[..later]
+                .iter()
+                .filter(|(_, earlier)| earlier.rule.requires.is_empty())
+                .find(|(_, earlier)| {
+                    earlier.rule.patterns.contains(pattern)
+                        || literal.as_ref().is_some_and(|literal| {
+                            RegexSet::new(&earlier.rule.patterns)
+                                .is_ok_and(|patterns| patterns.is_match(literal))
+                        })
+                });
+            if let Some((earlier, _)) = shadowing {
+                warnings.push(ConfigError {
+                    path: format!("{}[{}]", entry.patterns_path, index),
+                    message: format!(
+                        "`{}` of `{}` is shadowed by `{}`, which is tried first",
+                        pattern, name, earlier
+                    ),
+                });
+            }
+        }
+    }
+    warnings
+}
+
+/// The tags any rule gives
+fn known_tags(schema: PatternsYamlSchema) -> HashSet<String> {
+    let mut known: HashSet<String> = OWN_TAGS.iter().map(|tag| tag.to_string()).collect();
+    known.extend(schema.extensions.into_keys());
+    known.extend(schema.age_tags.into_keys());
+    known.extend(schema.filenames.into_iter().flat_map(|f| f.tags));
+    known.extend(schema.signatures.into_iter().flat_map(|s| s.tags));
+    known.extend(sniff::builtin_signatures().into_iter().flat_map(|s| s.tags));
+    let entries = schema.typical_files.into_entries().unwrap_or_default();
+    known.extend(entries.into_iter().flat_map(|e| e.rule.tags));
+    known
+}
+
+/// What is likely a mistake, though the configuration works
+fn warnings(schema: PatternsYamlSchema, known: Option<&HashSet<String>>) -> Vec<ConfigError> {
+    let mut warnings = vec![];
+
+    // an extension under two tags gets both
+    let mut tags_of: BTreeMap<String, Vec<(&String, usize)>> = BTreeMap::new();
+    for (tag, extensions) in schema.extensions.iter() {
+        for (index, extension) in extensions.iter().enumerate() {
+            tags_of
+                .entry(extension.to_ascii_lowercase())
+                .or_default()
+                .push((tag, index));
+        }
+    }
+    for (extension, tags) in tags_of.iter().filter(|(_, tags)| tags.len() > 1) {
+        for (tag, index) in tags.iter() {
+            let others: Vec<&str> = tags
+                .iter()
+                .filter(|(other, _)| other != tag)
+                .map(|(other, _)| other.as_str())
+                .collect();
+            warnings.push(ConfigError {
+                path: format!("extensions.{}[{}]", tag, index),
+                message: format!("`{}` is also under `{}`", extension, others.join("`, `")),
+            });
+        }
+    }
+
+    // the tags are only known when the layers fit together
+    for (synonym, tags) in schema.synonyms.iter().filter(|_| known.is_some()) {
+        for (index, tag) in tags.iter().enumerate() {
+            if !known.is_some_and(|known| known.contains(tag)) {
+                warnings.push(ConfigError {
+                    path: format!("synonyms.{}[{}]", synonym, index),
+                    message: format!("`{}` is not a tag given by any rule", tag),
+                });
+            }
+        }
+    }
+
+    // a malformed rule is an error already
+    let entries = schema.typical_files.into_entries().unwrap_or_default();
+    warnings.extend(shadowed_patterns(entries));
+    warnings
+}
+
+/// A layer with the rules it changes filled in from the merged layers,
+/// so a rule adding to one of another layer is not taken as incomplete
+fn complete(mut layer: Value, merged: &Value) -> Value {
+    let name = Value::String("name".to_owned());
+    if let (Value::Mapping(sections), Value::Mapping(merged)) = (&mut layer, merged) {
+        for (section, values) in sections.iter_mut() {
+            let rules = match (values, merged.get(section)) {
+                (Value::Sequence(values), Some(Value::Sequence(rules))) => values
+                    .iter_mut()
+                    .filter_map(|value| match value {
+                        Value::Mapping(value) => Some(value),
+                        _ => None,
+                    })
+                    .filter_map(|value| {
+                        let rule = rules.iter().find(|rule| {
+                            value.get(&name).is_some() && rule.get("name") == value.get(&name)
+                        });
+                        match rule {
+                            Some(Value::Mapping(rule)) => Some((value, rule)),
+                            _ => None,
+                        }
+                    }),
+                _ => continue,
+            };
+            for (value, rule) in rules {
+                for (key, field) in rule.iter() {
+                    if !value.contains_key(key) {
+                        value.insert(key.clone(), field.clone());
+                    }
+                }
+            }
+        }
+    }
+    layer
+}
+
+/// Print every error and warning in a configuration file with its line, and count them
+fn check_file(path: &Path, merged: &Value, known: Option<&HashSet<String>>) -> (usize, usize) {
+    let file = path.display();
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            println!("{}:0: error: cannot read it: {}", file, err);
+            return (1, 0);
+        }
+    };
+
+    let mut lines = Lines::default();
+    if let Err(err) = Parser::new(source.chars()).load(&mut lines, false) {
+        println!("{}:{}: error: {}", file, err.marker().line(), err);
+        return (1, 0);
+    }
+    let parsed = serde_yaml::from_str::<Value>(&source)
+        .and_then(|layer| serde_yaml::from_value::<PatternsYamlSchema>(complete(layer, merged)));
+    let schema = match parsed {
+        Ok(schema) => schema,
+        Err(err) => {
+            // the line is only known when the file is read on its own
+            let line = serde_yaml::from_str::<PatternsYamlSchema>(&source)
+                .err()
+                .and_then(|err| err.location())
+                .map_or(0, |location| location.line());
+            println!("{}:{}: error: {}", file, line, err);
+            return (1, 0);
+        }
+    };
+
+    let errors = schema.clone().compile().err().unwrap_or_default();
+    let warnings = warnings(schema, known);
+
+    let mut problems: Vec<(usize, &str, &ConfigError)> = errors
+        .iter()
+        .map(|error| (lines.line_of(&error.path), "error", error))
+        .chain(
+            warnings
+                .iter()
+                .map(|warning| (lines.line_of(&warning.path), "warning", warning)),
+        )
+        .collect();
+    problems.sort_by_key(|(line, _, _)| *line);
+    for (line, severity, problem) in problems {
+        println!("{}:{}: {}: {}", file, line, severity, problem);
+    }
+
+    match (errors.len(), warnings.len()) {
+        (0, 0) => println!("{} is fine", file),
+        (0, count) => println!("{} has no errors, and {} warnings", file, count),
+        (errors, warnings) => println!("{} has {} errors and {} warnings", file, errors, warnings),
+    }
+    (errors.len(), warnings.len())
+}
+
+/// Read the layers of the configuration with the files they include,
+/// and print every error and warning in them with its line
+pub fn check(config_paths: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    if config_paths.is_empty() {
+        println!("No configuration file, the built-in rules are used");
+        return Ok(());
+    }
+    let mut files = vec![];
+    let mut merged = builtin::layer();
+    let mut seen = HashSet::new();
+    for config_path in config_paths {
+        for (file, layer) in layer::read_layers(config_path, &mut seen)? {
+            merged = layer::merge(merged, layer)?;
+            files.push(file);
+        }
+    }
+    let known = serde_yaml::from_value::<PatternsYamlSchema>(merged.clone())
+        .map(known_tags)
+        .ok();
+
+    let mut errors = 0;
+    for file in files.iter() {
+        errors += check_file(file, &merged, known.as_ref()).0;
+    }
+    // the layers can be fine on their own and not over the built-in rules
+    if errors == 0 {
+        if let Err(merged_errors) = config::compile_layers(&merged) {
+            for error in merged_errors.iter() {
+                println!("merged layers: error: {}", error);
+            }
+            errors += merged_errors.len();
+        }
+    }
+    match errors {
+        0 => Ok(()),
+        count => Err(format!("the configuration has {} errors", count).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+extensions:
+  image: [jpg, png]
+  video:
+    - mkv
+    - JPG
+typical_files:
+  - name: games
+    tags: [game]
+    patterns:
+      - '^README\\.txt$'
+      - '\\.exe$'
+  - tags: [document]
+    patterns: ['^README\\.txt$']
+";
+
+    fn lines(source: &str) -> Lines {
+        let mut lines = Lines::default();
+        Parser::new(source.chars()).load(&mut lines, false).unwrap();
+        lines
+    }
+
+    #[test]
+    fn paths_to_lines() {
+        let lines = lines(SOURCE);
+        assert_eq!(lines.line_of("extensions"), 1);
+        assert_eq!(lines.line_of("extensions.image[1]"), 2);
+        assert_eq!(lines.line_of("extensions.video"), 3);
+        assert_eq!(lines.line_of("extensions.video[1]"), 5);
+        assert_eq!(lines.line_of("typical_files[0]"), 7);
+        assert_eq!(lines.line_of("typical_files[0].tags[0]"), 8);
+        assert_eq!(lines.line_of("typical_files[0].patterns[1]"), 11);
+        assert_eq!(lines.line_of("typical_files[1].patterns[0]"), 13);
+    }
+
+    #[test]
+    fn missing_paths_go_to_what_holds_them() {
+        let lines = lines(SOURCE);
+        assert_eq!(lines.line_of("typical_files[1].priority"), 12);
+        assert_eq!(lines.line_of("typical_files[0].patterns[5]"), 9);
+        assert_eq!(lines.line_of("signatures[0]"), 0);
+    }
+
+    #[test]
+    fn warnings_where_they_are() {
+        let schema: PatternsYamlSchema = serde_yaml::from_str(SOURCE).unwrap();
+        let lines = lines(SOURCE);
+        let mut found: Vec<(usize, String)> = warnings(schema, None)
+            .iter()
+            .map(|warning| (lines.line_of(&warning.path), warning.message.clone()))
+            .collect();
+        found.sort();
+        assert_eq!(
+            found,
+            [
+                (2, "`jpg` is also under `video`".to_owned()),
+                (5, "`jpg` is also under `image`".to_owned()),
+                (
+                    13,
+                    "`^README\\.txt$` of `document` is shadowed by `games`, which is tried first"
+                        .to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn literal_names() {
+        assert_eq!(literal_name(r"^Game\.exe$").as_deref(), Some("Game.exe"));
+        assert_eq!(literal_name("^Makefile$").as_deref(), Some("Makefile"));
+        assert_eq!(literal_name(r"\.exe$"), None);
+        assert_eq!(literal_name(r"^\d+$"), None);
+        assert_eq!(literal_name("^(a|b)$"), None);
+    }
+}
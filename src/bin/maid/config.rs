@@ -1,88 +1,491 @@
-use clap::{command, Parser};
+use crate::builtin;
+use crate::datatype::EntryMetadata;
+use crate::filter::{self, Bound};
+use crate::garbage::{self, GarbageRules};
+use crate::layer;
+use crate::query::Query;
+use crate::series::SeriesRule;
+use crate::sniff::{self, Signature};
+use crate::template::Template;
+use crate::typical::TypicalRule;
+use clap::{Parser, Subcommand, ValueEnum};
 use once_cell::sync::Lazy;
 use regex::{Regex, RegexSet};
 use serde::Deserialize;
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
-use std::string::String;
 
-#[derive(Debug, Deserialize)]
-struct PatternsYamlSchema {
-    typical_files: HashMap<String, Vec<String>>,
-    extensions: HashMap<String, Vec<String>>,
-    filenames: Vec<FilenamePattern>,
-    synonyms: HashMap<String, Vec<String>>,
+/// Every section can be left out, as a layer only holds what it adds or overrides
+#[derive(Clone, Debug, Deserialize)]
+pub struct PatternsYamlSchema {
+    #[serde(default)]
+    pub typical_files: TypicalFilesSchema,
+    #[serde(default)]
+    pub extensions: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub filenames: Vec<FilenamePattern>,
+    #[serde(default)]
+    pub synonyms: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub age_tags: BTreeMap<String, AgeTagSchema>,
+    #[serde(default)]
+    pub signatures: Vec<SignatureSchema>,
+    garbage: Option<GarbageSchema>,
+    #[serde(default)]
+    series: Vec<SeriesSchema>,
 }
 
-#[derive(Debug, Deserialize)]
-struct FilenamePattern {
-    tags: Vec<String>,
+/// A problem in the configuration, at a path like `typical_files[2].patterns[0]`
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    fn new(path: impl Into<String>, message: impl Display) -> Self {
+        ConfigError {
+            path: path.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            return write!(f, "{}", self.message);
+        }
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// The rules telling what a directory is by the files in it, a list of rules,
+/// or a map of a tag to its patterns as before, tried in the order they are written
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum TypicalFilesSchema {
+    Rules(Vec<TypicalRuleSchema>),
+    Tags(serde_yaml::Mapping),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TypicalRuleSchema {
+    pub name: Option<String>,
+    pub tags: Vec<String>,
+    /// Higher ones are tried first, 0 by default
+    #[serde(default)]
+    pub priority: i64,
+    /// Regexes on the file names, any of them makes the rule match
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Regexes on the file names, each of them has to match for the rule to match
+    #[serde(default)]
+    pub requires: Vec<String>,
+}
+
+/// A typical files rule, and where it and its patterns are in the configuration
+pub struct TypicalEntry {
+    pub path: String,
+    pub patterns_path: String,
+    pub rule: TypicalRuleSchema,
+}
+
+impl TypicalEntry {
+    /// What the rule is called in messages, its name, its tags or its place in the list
+    pub fn name(&self, index: usize) -> String {
+        match self.rule.name {
+            Some(ref name) => name.clone(),
+            None if self.rule.tags.is_empty() => format!("#{}", index + 1),
+            None => self.rule.tags.join(", "),
+        }
+    }
+}
+
+impl Default for TypicalFilesSchema {
+    fn default() -> Self {
+        TypicalFilesSchema::Rules(vec![])
+    }
+}
+
+impl TypicalFilesSchema {
+    /// The rules in the order they are written, the old form made into rules
+    pub fn into_entries(self) -> Result<Vec<TypicalEntry>, ConfigError> {
+        match self {
+            TypicalFilesSchema::Rules(rules) => Ok(rules
+                .into_iter()
+                .enumerate()
+                .map(|(index, rule)| TypicalEntry {
+                    path: format!("typical_files[{}]", index),
+                    patterns_path: format!("typical_files[{}].patterns", index),
+                    rule,
+                })
+                .collect()),
+            TypicalFilesSchema::Tags(tags) => tags
+                .into_iter()
+                .map(|(tag, patterns)| {
+                    let tag: String = serde_yaml::from_value(tag)
+                        .map_err(|e| ConfigError::new("typical_files", e))?;
+                    let path = format!("typical_files.{}", tag);
+                    let patterns = serde_yaml::from_value(patterns)
+                        .map_err(|e| ConfigError::new(path.clone(), e))?;
+                    Ok(TypicalEntry {
+                        patterns_path: path.clone(),
+                        path,
+                        rule: TypicalRuleSchema {
+                            name: Some(tag.clone()),
+                            tags: vec![tag],
+                            priority: 0,
+                            patterns,
+                            requires: vec![],
+                        },
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    fn rules(self, errors: &mut Vec<ConfigError>) -> Vec<TypicalRule> {
+        let entries = match self.into_entries() {
+            Ok(entries) => entries,
+            Err(err) => {
+                errors.push(err);
+                return vec![];
+            }
+        };
+
+        let mut rules = vec![];
+        for (index, entry) in entries.into_iter().enumerate() {
+            let name = entry.name(index);
+            if entry.rule.tags.is_empty() {
+                errors.push(ConfigError::new(entry.path.clone(), "no tags"));
+            }
+            if entry.rule.patterns.is_empty() && entry.rule.requires.is_empty() {
+                errors.push(ConfigError::new(entry.path.clone(), "no patterns"));
+            }
+            let patterns = compile_all(&entry.patterns_path, &entry.rule.patterns, errors);
+            let requires = compile_all(
+                &format!("{}.requires", entry.path),
+                &entry.rule.requires,
+                errors,
+            );
+            if let (Some(_), Some(requires)) = (patterns, requires) {
+                rules.push(TypicalRule {
+                    // every one of them compiles on its own
+                    patterns: RegexSet::new(&entry.rule.patterns).unwrap(),
+                    requires,
+                    name,
+                    tags: entry.rule.tags,
+                    priority: entry.rule.priority,
+                });
+            }
+        }
+        // a stable sort, so the ones of the same priority stay in order
+        rules.sort_by_key(|rule| Reverse(rule.priority));
+        rules
+    }
+}
+
+/// Compile each of the regexes, the ones that do not compile are told by their place in the list
+fn compile_all(
+    path: &str,
+    patterns: &[String],
+    errors: &mut Vec<ConfigError>,
+) -> Option<Vec<Regex>> {
+    let mut compiled = vec![];
+    let mut valid = true;
+    for (index, pattern) in patterns.iter().enumerate() {
+        match Regex::new(pattern) {
+            Ok(regex) => compiled.push(regex),
+            Err(err) => {
+                errors.push(ConfigError::new(format!("{}[{}]", path, index), err));
+                valid = false;
+            }
+        }
+    }
+    valid.then_some(compiled)
+}
+
+fn default_min_count() -> usize {
+    2
+}
+
+/// Sibling files with names in a row, kept together in a folder named after their prefix
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SeriesSchema {
+    name: String,
+    /// A regex on the file name, with a `prefix` group for what the files have in common
     pattern: String,
+    #[serde(default = "default_min_count")]
+    min_count: usize,
+}
+
+/// What is tagged as garbage, every rule is off unless given
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GarbageSchema {
+    empty: bool,
+    broken_links: bool,
+    /// The least number of hex digits in a name
+    hex_names: Option<usize>,
+    /// Names with `*` and `?` as wildcards
+    names: Vec<String>,
+}
+
+/// A magic number to tell files by their content with --sniff
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SignatureSchema {
+    name: String,
+    pub tags: Vec<String>,
+    #[serde(default)]
+    offset: usize,
+    /// Hex bytes separated by spaces, `??` for any byte
+    magic: String,
+    #[serde(default)]
+    extensions: Vec<String>,
+}
+
+/// The limits an entry has to be within to get an age tag, as in --older-than and so on
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgeTagSchema {
+    older_than: Option<String>,
+    newer_than: Option<String>,
+    accessed_before: Option<String>,
+    larger_than: Option<String>,
+    smaller_than: Option<String>,
+}
+
+impl AgeTagSchema {
+    fn bounds(self) -> Result<Vec<Bound>, String> {
+        let time = |value: Option<String>| value.as_deref().map(filter::parse_time).transpose();
+        let size = |value: Option<String>| value.as_deref().map(filter::parse_size).transpose();
+        Ok(filter::bounds(
+            time(self.older_than)?,
+            time(self.newer_than)?,
+            time(self.accessed_before)?,
+            size(self.larger_than)?,
+            size(self.smaller_than)?,
+        ))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FilenamePattern {
+    pub tags: Vec<String>,
+    pub pattern: String,
 }
 
 #[derive(Debug)]
 pub struct Patterns {
-    pub typical_files_re: HashMap<String, RegexSet>,
+    /// Sorted by priority, the first one matching counts
+    pub typical_files: Vec<TypicalRule>,
     pub filenames_re: Vec<(Vec<String>, Regex)>,
     pub extensions: HashMap<String, HashSet<String>>,
     pub synonyms: HashMap<String, HashSet<String>>,
+    /// Tags given by the age of an entry, they are not saved as they change over time
+    pub age_tags: Vec<(String, Vec<Bound>)>,
+    /// The ones in the config come before the built-in ones
+    pub signatures: Vec<Signature>,
+    pub garbage: GarbageRules,
+    /// Tried in order, the first one matching a name counts
+    pub series: Vec<SeriesRule>,
 }
 
-pub fn load_patterns<T>(config_path: T) -> Patterns
-where
-    T: AsRef<Path> + Clone,
-{
-    let patterns_yaml = std::fs::read_to_string::<T>(config_path.clone());
-    if patterns_yaml.is_err() {
-        eprintln!(
-            "Error: Could not find patterns file at {}.",
-            config_path.as_ref().display()
-        );
-        std::process::exit(1);
+impl Patterns {
+    pub fn age_tags_of<'a>(
+        &'a self,
+        metadata: &'a EntryMetadata,
+    ) -> impl Iterator<Item = &'a String> + 'a {
+        self.age_tags
+            .iter()
+            .filter(|(_, bounds)| bounds.iter().all(|bound| bound.matches(metadata)))
+            .map(|(tag, _)| tag)
     }
-    let patterns: Result<PatternsYamlSchema, serde_yaml::Error> =
-        serde_yaml::from_str(&patterns_yaml.unwrap());
-    if let Err(err) = patterns {
-        eprintln!("{}", err.to_string());
 
-        std::process::exit(1);
+    pub fn is_age_tag(&self, tag: &str) -> bool {
+        self.age_tags.iter().any(|(age_tag, _)| age_tag == tag)
     }
-    let patterns = patterns.unwrap();
+}
 
-    let typical_files_re = patterns
-        .typical_files
-        .into_iter()
-        .map(|(key, patterns)| (key, RegexSet::new(patterns).unwrap()))
-        .collect();
-    let filenames_re = patterns
-        .filenames
+/// Put the layers of the configuration over the built-in rules, and compile them.
+/// The merged layers are kept, for the configuration of a directory to be put over them.
+pub fn load_patterns(config_paths: &[PathBuf]) -> (serde_yaml::Value, Patterns) {
+    let mut merged = builtin::layer();
+    for config_path in config_paths {
+        merged = match merge_file(merged, config_path) {
+            Ok(merged) => merged,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                std::process::exit(1);
+            }
+        };
+    }
+    match compile_layers(&merged) {
+        Ok(patterns) => (merged, patterns),
+        Err(errors) => {
+            for err in errors {
+                eprintln!("Error: Invalid {}", err);
+            }
+            let files: Vec<String> = config_paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            eprintln!(
+                "Call `maid config check` to find them in {}",
+                files.join(", ")
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Put a configuration file, after the ones it includes, over the merged layers
+pub fn merge_file(
+    merged: serde_yaml::Value,
+    config_path: &Path,
+) -> Result<serde_yaml::Value, String> {
+    layer::read_layers(config_path, &mut HashSet::new())?
         .into_iter()
-        .map(|filename_pattern| {
-            (
-                filename_pattern.tags,
-                Regex::new(&filename_pattern.pattern).unwrap(),
+        .try_fold(merged, |merged, (_, layer)| layer::merge(merged, layer))
+}
+
+/// Compile the rules of merged layers
+pub fn compile_layers(merged: &serde_yaml::Value) -> Result<Patterns, Vec<ConfigError>> {
+    serde_yaml::from_value::<PatternsYamlSchema>(merged.clone())
+        .map_err(|err| vec![ConfigError::new("", err)])?
+        .compile()
+}
+
+impl PatternsYamlSchema {
+    /// Compile the rules, with every problem found on the way
+    pub fn compile(self) -> Result<Patterns, Vec<ConfigError>> {
+        let mut errors = vec![];
+
+        let typical_files = self.typical_files.rules(&mut errors);
+        let filenames_re = self
+            .filenames
+            .into_iter()
+            .enumerate()
+            .filter_map(
+                |(index, filename_pattern)| match Regex::new(&filename_pattern.pattern) {
+                    Ok(pattern) => Some((filename_pattern.tags, pattern)),
+                    Err(err) => {
+                        errors.push(ConfigError::new(
+                            format!("filenames[{}].pattern", index),
+                            err,
+                        ));
+                        None
+                    }
+                },
             )
-        })
-        .collect();
-    let extensions = patterns
-        .extensions
-        .into_iter()
-        .map(|(key, values)| (key, values.into_iter().collect::<HashSet<_>>()))
-        .collect();
+            .collect();
+        let extensions = self
+            .extensions
+            .into_iter()
+            .map(|(key, values)| (key, values.into_iter().collect::<HashSet<_>>()))
+            .collect();
 
-    let synonyms = patterns
-        .synonyms
-        .into_iter()
-        .map(|(key, values)| (key, values.into_iter().collect::<HashSet<_>>()))
-        .collect();
+        let synonyms = self
+            .synonyms
+            .into_iter()
+            .map(|(key, values)| (key, values.into_iter().collect::<HashSet<_>>()))
+            .collect();
+
+        let age_tags = self
+            .age_tags
+            .into_iter()
+            .filter_map(|(tag, age_tag)| match age_tag.bounds() {
+                Ok(bounds) => Some((tag, bounds)),
+                Err(err) => {
+                    errors.push(ConfigError::new(format!("age_tags.{}", tag), err));
+                    None
+                }
+            })
+            .collect();
+
+        let mut signatures: Vec<Signature> = self
+            .signatures
+            .into_iter()
+            .enumerate()
+            .filter_map(
+                |(index, signature)| match sniff::parse_magic(&signature.magic) {
+                    Ok(magic) => Some(Signature {
+                        name: signature.name,
+                        tags: signature.tags,
+                        offset: signature.offset,
+                        magic,
+                        extensions: signature.extensions,
+                    }),
+                    Err(err) => {
+                        errors.push(ConfigError::new(
+                            format!("signatures[{}].magic", index),
+                            err,
+                        ));
+                        None
+                    }
+                },
+            )
+            .collect();
+        signatures.extend(sniff::builtin_signatures());
 
-    Patterns {
-        typical_files_re,
-        filenames_re,
-        extensions,
-        synonyms,
+        let garbage = self.garbage.unwrap_or_default();
+        let garbage = GarbageRules {
+            empty: garbage.empty,
+            broken_links: garbage.broken_links,
+            hex_names: garbage.hex_names,
+            names: garbage
+                .names
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, name)| match garbage::glob_to_regex(&name) {
+                    Ok(re) => Some((name, re)),
+                    Err(err) => {
+                        errors.push(ConfigError::new(format!("garbage.names[{}]", index), err));
+                        None
+                    }
+                })
+                .collect(),
+        };
+
+        let series = self
+            .series
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, series)| match Regex::new(&series.pattern) {
+                Ok(pattern) => Some(SeriesRule {
+                    name: series.name,
+                    pattern,
+                    min_count: series.min_count.max(2),
+                }),
+                Err(err) => {
+                    errors.push(ConfigError::new(format!("series[{}].pattern", index), err));
+                    None
+                }
+            })
+            .collect();
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Patterns {
+            typical_files,
+            filenames_re,
+            extensions,
+            synonyms,
+            age_tags,
+            signatures,
+            garbage,
+            series,
+        })
     }
 }
 
@@ -100,9 +503,8 @@ static SHELLS: Lazy<[String; 6]> = Lazy::new(|| {
 pub fn find_shell() -> Option<(String, String)> {
     let shell: Option<String> = SHELLS
         .iter()
-        .filter(|shell| Path::new(shell).exists())
-        .next()
-        .and_then(|s| Some(s.to_owned()));
+        .find(|shell| Path::new(shell).exists())
+        .map(|s| s.to_owned());
 
     let arg1 = if env::var("COMSPEC")
         .unwrap_or_default()
@@ -113,16 +515,97 @@ pub fn find_shell() -> Option<(String, String)> {
         "-c"
     };
 
-    if shell.is_some() {
-        Some((shell.unwrap(), arg1.to_owned()))
-    } else {
-        None
-    }
+    shell.map(|shell| (shell, arg1.to_owned()))
+}
+
+/// What to do when the destination of --mv or --cp is taken
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ConflictPolicy {
+    /// Leave the entry where it is
+    Skip,
+    /// Replace what is at the destination
+    Overwrite,
+    /// Append a numeric suffix to the name
+    Rename,
+    /// Skip if the contents are identical, otherwise rename
+    HashCompare,
+}
+
+/// Which copy --dedupe keeps, the others are moved to the trash
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum DedupePolicy {
+    /// Keep the copy modified first
+    KeepOldest,
+    /// Keep the copy modified last
+    KeepNewest,
+    /// Keep the copy with the shortest path
+    KeepShortestPath,
+    /// Keep the oldest copy, and replace the others with hard links to it
+    Hardlink,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MaidCommand {
+    /// Reverse the moves and copies of a previous run.
+    Undo {
+        /// The run to undo. If not specified, the latest run will be undone.
+        run_id: Option<String>,
+    },
+    /// Show how an entry is tagged, by which rules, and the rules tried that did not match.
+    Explain {
+        /// The file or directory to explain.
+        path: PathBuf,
+    },
+    /// Look into the configuration file.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Look into the trash can.
+    Trash {
+        #[command(subcommand)]
+        command: TrashCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Compile every rule, and list the errors and likely mistakes with their lines.
+    Check,
+    /// Write the built-in rules to -c or $XDG_CONFIG_HOME/maidsweep/config.yaml, to edit them.
+    Init {
+        /// Overwrite the file if it exists.
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show what the configuration files add to, remove from or change in the built-in rules.
+    Diff,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TrashCommand {
+    /// List the entries in the trash.
+    List,
+    /// Put entries back where they were.
+    Restore {
+        /// The names in the trash, or the original paths.
+        #[arg(required = true, value_name = "ITEM")]
+        items: Vec<String>,
+    },
+    /// Permanently delete everything in the trash.
+    Empty,
+}
+
+fn default_jobs() -> NonZeroUsize {
+    std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about = "Call the maid sweeper", long_about=None)]
 pub struct MaidConfig {
+    #[command(subcommand)]
+    pub command: Option<MaidCommand>,
+
     /// If set, the program will store the metadata in a MongoDB database when sweeping.
     #[arg(
         long,
@@ -131,8 +614,8 @@ pub struct MaidConfig {
     )]
     pub debug: bool,
 
-    /// Whether or not to use MongoDB. If false, the program will scan the directories
-    #[arg(long, default_value = "false")]
+    /// Whether or not to read the entries from the store. If false, the program will scan the directories
+    #[arg(long, alias = "use-store", default_value = "false")]
     pub use_mongodb: bool,
 
     #[arg(
@@ -142,7 +625,13 @@ pub struct MaidConfig {
     )]
     pub mongodb_host: String,
 
-    /// The path to the configuration file. By default it is ~/.maidsweeprs.yaml.
+    /// Where the entries are saved and read, `mongodb://...` or `sqlite:///path/to/index.db`.
+    /// By default it is the MongoDB server given by --mongodb-host.
+    #[arg(long, value_name = "URL")]
+    pub store: Option<String>,
+
+    /// The path to the configuration file, in place of $XDG_CONFIG_HOME/maidsweep/config.yaml
+    /// and ~/.maidsweep.yaml. It is put over the built-in rules.
     #[arg(short = 'c', long = "config")]
     pub config_file: Option<String>,
 
@@ -150,6 +639,37 @@ pub struct MaidConfig {
     #[arg(short = 't', long = "tag", value_name = "TAG")]
     pub tags: Option<Vec<String>>,
 
+    /// Only sweep entries that have all the tags given with -t, instead of any of them.
+    #[arg(long)]
+    pub all_tags: bool,
+
+    /// Only sweep entries matching a query like '(image | video) & stale & size > 100M'.
+    /// Tags are combined with & (and), | (or), ! (not) and parentheses, and compared fields are
+    /// size, modified, accessed (in the formats of --larger-than and --older-than) and age.
+    #[arg(short = 'q', long, value_name = "QUERY", value_parser = Query::parse_arg)]
+    pub query: Option<Query>,
+
+    /// Only sweep entries last modified before this, an age like 1y, 6mo, 2w, 3d, 12h or a date like 2023-01-31.
+    /// A directory is as old as the newest file in it.
+    #[arg(long, value_name = "AGE", value_parser = filter::parse_time)]
+    pub older_than: Option<u64>,
+
+    /// Only sweep entries last modified after this, in the same format as --older-than.
+    #[arg(long, value_name = "AGE", value_parser = filter::parse_time)]
+    pub newer_than: Option<u64>,
+
+    /// Only sweep entries last accessed before this, in the same format as --older-than.
+    #[arg(long, value_name = "AGE", value_parser = filter::parse_time)]
+    pub accessed_before: Option<u64>,
+
+    /// Only sweep entries larger than this, like 500K, 100M or 1G. A directory counts everything in it.
+    #[arg(long, value_name = "SIZE", value_parser = filter::parse_size)]
+    pub larger_than: Option<u64>,
+
+    /// Only sweep entries smaller than this, in the same format as --larger-than.
+    #[arg(long, value_name = "SIZE", value_parser = filter::parse_size)]
+    pub smaller_than: Option<u64>,
+
     /// The paths to scan and label. If not specified, the current directory will be used.
     #[arg(required = false,
         num_args = 1..,
@@ -157,6 +677,10 @@ pub struct MaidConfig {
         value_name = "PATH")]
     pub paths: Option<Vec<PathBuf>>,
 
+    /// Read the first bytes of files to tell what they are when the extension is missing or wrong.
+    #[arg(long)]
+    pub sniff: bool,
+
     /// If set to true, hidden files will be considered when sweeping. For UNIX only.
     #[arg(short = 'H', long = "hidden", default_value = "false")]
     pub hidden: bool,
@@ -165,11 +689,12 @@ pub struct MaidConfig {
     #[arg(long = "cp", value_name = "PATH")]
     pub copy_to: Option<PathBuf>,
 
-    /// Save the metadata to mongodb.
-    #[arg(long = "save", value_name = "MONGODB_URI")]
+    /// Save the metadata to the store.
+    #[arg(long = "save")]
     pub save: bool,
 
     /// The command to execute. Like in fd -x or find -exec, you can use {} to represent the path.
+    /// It is run directly without a shell, unless --exec-shell is given.
     #[arg(
         short = 'x',
         long = "exec",
@@ -180,11 +705,186 @@ pub struct MaidConfig {
     )]
     pub exec_args: Option<Vec<OsString>>,
 
+    /// Like --exec, but the command is called once with all the entries, or in as few batches as
+    /// possible. Entries are grouped by their first tag, so {1} is the same for a batch.
+    /// Arguments with other placeholders are repeated for each entry.
+    #[arg(
+        short = 'X',
+        long = "exec-batch",
+        num_args = 1..,
+        allow_hyphen_values = true,
+        value_name = "EXEC_ARG",
+        value_terminator = ";",
+        conflicts_with = "exec_args"
+    )]
+    pub exec_batch_args: Option<Vec<OsString>>,
+
+    /// Run the command of --exec or --exec-batch in a shell, so that pipes and redirections work.
+    /// The arguments are quoted, except for operators like `|`, `&&` or `>`.
+    #[arg(long = "exec-shell")]
+    pub exec_shell: bool,
+
     /// Can be used instead of --exec to move files to a directory.
     #[arg(long = "mv", value_name = "PATH")]
     pub move_to: Option<PathBuf>,
 
-    /// Can be used instead of --exec to delete files.
-    #[arg(long = "rm")]
+    /// How --mv and --cp lay out the destination, like `{1}/{year}/{month}`. The first tag `{1}` by default.
+    /// Besides the placeholders of --exec, `{year}`, `{month}`, `{ext}` and `{root}` can be used.
+    #[arg(long = "layout", value_name = "TEMPLATE", value_parser = Template::parse_arg)]
+    pub layout: Option<Template>,
+
+    /// What to do when a file with the same name is already at the destination of --mv or --cp.
+    #[arg(long = "on-conflict", value_enum, default_value = "rename")]
+    pub on_conflict: ConflictPolicy,
+
+    /// Can be used instead of --exec to move files to the trash.
+    #[arg(long = "trash")]
+    pub trash: bool,
+
+    /// Can be used instead of --exec to delete files permanently. Needs --force.
+    #[arg(long = "rm", requires = "force")]
     pub delete: bool,
+
+    /// Can be used instead of --exec to get rid of the copies of files with the same content.
+    /// Only the entries tagged `duplicate` are touched, the copy that is kept is not.
+    #[arg(long = "dedupe", value_enum, value_name = "POLICY")]
+    pub dedupe: Option<DedupePolicy>,
+
+    /// Confirm that files are deleted permanently with --rm.
+    #[arg(long = "force")]
+    pub force: bool,
+
+    /// How many file operations or commands can run at once, and how many directories can be read at once.
+    /// By default it is the number of CPUs.
+    #[arg(short = 'j', long = "jobs", value_name = "N", default_value_t = default_jobs())]
+    pub jobs: NonZeroUsize,
+
+    /// Print what would be done to each entry instead of doing it.
+    #[arg(short = 'n', long = "dry-run", default_value = "false")]
+    pub dry_run: bool,
+}
+
+impl MaidConfig {
+    /// The directories to scan, the current one if none is given
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.paths
+            .clone()
+            .unwrap_or_else(|| vec![PathBuf::from(".")])
+    }
+
+    /// The URL of the store, --store or else --mongodb-host
+    pub fn store_url(&self) -> &str {
+        self.store.as_deref().unwrap_or(&self.mongodb_host)
+    }
+
+    /// $XDG_CONFIG_HOME/maidsweep/config.yaml, or ~/.config/maidsweep/config.yaml when it is not set
+    fn xdg_config_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+            .map(|path| path.join("maidsweep").join("config.yaml"))
+    }
+
+    /// The configuration files put over the built-in rules, -c or else the ones of
+    /// $XDG_CONFIG_HOME/maidsweep/config.yaml and ~/.maidsweep.yaml there are
+    pub fn config_paths(&self) -> Vec<PathBuf> {
+        if let Some(ref path) = self.config_file {
+            return vec![PathBuf::from(path)];
+        }
+        [
+            Self::xdg_config_path(),
+            dirs::home_dir().map(|home| home.join(".maidsweep.yaml")),
+        ]
+        .into_iter()
+        .flatten()
+        .filter(|path| path.exists())
+        .collect()
+    }
+
+    /// Where `maid config init` writes the built-in rules, -c or else
+    /// $XDG_CONFIG_HOME/maidsweep/config.yaml, when there is one
+    pub fn init_path(&self) -> Result<PathBuf, String> {
+        match self.config_file {
+            Some(ref path) => Ok(PathBuf::from(path)),
+            None => Self::xdg_config_path().ok_or_else(|| {
+                "Cannot find the home directory, give -c to choose where to write the rules"
+                    .to_owned()
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typical;
+
+    fn typical_rules(yaml: &str) -> Vec<TypicalRule> {
+        let schema: TypicalFilesSchema = serde_yaml::from_str(yaml).unwrap();
+        let mut errors = vec![];
+        let rules = schema.rules(&mut errors);
+        assert!(errors.is_empty());
+        rules
+    }
+
+    /// The tags of the first rule matching the names, and the rules tried before it
+    fn typical_of(rules: &[TypicalRule], names: &[&str]) -> (Option<Vec<String>>, Vec<String>) {
+        let names: Vec<(String, PathBuf)> = names
+            .iter()
+            .map(|name| (name.to_string(), Path::new("/d").join(name)))
+            .collect();
+        let mut missed = vec![];
+        let found = typical::find_typical(rules, &names, |rule, _| missed.push(rule.name.clone()));
+        (found.map(|(rule, _)| rule.tags.clone()), missed)
+    }
+
+    const RULES: &str = "
+- name: documents
+  tags: [document]
+  patterns: ['^README\\.txt$']
+- name: games
+  tags: [game]
+  priority: 10
+  patterns: ['^README\\.txt$']
+  requires: ['\\.exe$']
+- name: version control
+  tags: [project]
+  priority: 30
+  patterns: ['^\\.git$']
+- name: software
+  tags: [software]
+  patterns: ['\\.exe$']
+";
+
+    #[test]
+    fn higher_priorities_first_then_in_order() {
+        let rules = typical_rules(RULES);
+        let names: Vec<&str> = rules.iter().map(|rule| rule.name.as_str()).collect();
+        assert_eq!(names, ["version control", "games", "documents", "software"]);
+
+        let (tags, missed) = typical_of(&rules, &["README.txt", "game.exe"]);
+        assert_eq!(tags, Some(vec!["game".to_owned()]));
+        assert_eq!(missed, ["version control"]);
+        let (tags, _) = typical_of(&rules, &[".git", "README.txt", "game.exe"]);
+        assert_eq!(tags, Some(vec!["project".to_owned()]));
+    }
+
+    #[test]
+    fn required_names_must_all_be_there() {
+        let rules = typical_rules(RULES);
+        let (tags, missed) = typical_of(&rules, &["README.txt"]);
+        assert_eq!(tags, Some(vec!["document".to_owned()]));
+        assert_eq!(missed, ["version control", "games"]);
+        let (tags, _) = typical_of(&rules, &["notes.md"]);
+        assert_eq!(tags, None);
+    }
+
+    #[test]
+    fn the_old_form_has_no_priorities() {
+        let rules = typical_rules("{game: ['\\.exe$'], document: ['^README\\.txt$']}");
+        let (tags, missed) = typical_of(&rules, &["README.txt", "game.exe"]);
+        assert_eq!(tags, Some(vec!["game".to_owned()]));
+        assert!(missed.is_empty());
+    }
 }
@@ -1,32 +1,44 @@
 use crate::config::{self, MaidConfig};
-use dirs;
-use mongodb::{options::ClientOptions, Client};
-use std::{error::Error, path::PathBuf};
+use crate::datatype::FileMeta;
+use crate::duplicate::DUPLICATE_TAG;
+use crate::explain::Trace;
+use crate::journal::Journal;
+use crate::query::Query;
+use crate::store::{self, Store};
+use crate::summary::RunSummary;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::Semaphore;
 
-pub struct MongoDBContext {
-    pub client: Client,
-    pub database: mongodb::Database,
-}
-
-impl MongoDBContext {
-    pub async fn new(config: &MaidConfig) -> Result<Self, Box<dyn Error>> {
-        let options: ClientOptions =
-            ClientOptions::parse(format!("{}", config.mongodb_host)).await?;
-        let client = Client::with_options(options)?;
-        let database = client.database("maidsweep");
-
-        Ok(MongoDBContext { client, database })
-    }
-
-    pub fn get_db(&self) -> &mongodb::Database {
-        &self.database
-    }
-}
+/// The layers of a directory with a configuration of its own, and the rules of them
+type Local = (Arc<serde_yaml::Value>, Arc<config::Patterns>);
 
 pub struct MaidContext {
     pub config: MaidConfig,
-    pub patterns: config::Patterns,
-    pub mongodb: Option<MongoDBContext>,
+    /// The rules of the configuration layers, everywhere a directory has none of its own
+    pub patterns: Arc<config::Patterns>,
+    /// The configuration layers merged, for the one of a directory to be put over
+    pub layers: Arc<serde_yaml::Value>,
+    /// The layers and rules of the directories with a configuration of their own
+    pub local_patterns: RwLock<HashMap<PathBuf, Local>>,
+    pub store: Option<Box<dyn Store>>,
+    /// Which entries to sweep, by their tags, age and size
+    pub query: Query,
+    pub journal: Journal,
+    pub summary: RunSummary,
+    /// Limits the file operations and commands running at once
+    pub jobs: Semaphore,
+    /// Limits the directories being walked at once
+    pub walkers: Arc<Semaphore>,
+    /// Entries for --exec-batch, by their first tag
+    pub exec_batch: Mutex<BTreeMap<String, Vec<FileMeta>>>,
+    /// Files held back until every one is seen, when duplicates are looked for
+    pub duplicates: Option<Mutex<Vec<FileMeta>>>,
+    /// The rules tried on the way to an entry, when it is explained instead of swept
+    pub trace: Option<Trace>,
 }
 
 impl MaidContext {
@@ -38,34 +50,130 @@ impl MaidContext {
         &self.config
     }
 
-    pub fn get_db(&self) -> Option<&mongodb::Database> {
-        if let Some(ref mongodb) = self.mongodb {
-            Some(mongodb.get_db())
-        } else {
-            None
+    pub fn get_store(&self) -> Option<&dyn Store> {
+        self.store.as_deref()
+    }
+
+    /// Note a rule tried on an entry, if it is on the way to the one explained
+    pub fn tried(&self, path: &Path, section: &'static str, matched: bool, rule: impl Display) {
+        if let Some(ref trace) = self.trace {
+            trace.tried(path, section, matched, rule);
         }
     }
 
-    pub async fn new(config: MaidConfig) -> Self {
-        let patterns = if let Some(ref path) = config.config_file {
-            config::load_patterns(path)
+    /// Whether the walk goes on with an entry, always unless another one is explained
+    pub fn follows(&self, path: &Path) -> bool {
+        self.trace.as_ref().is_none_or(|trace| trace.follows(path))
+    }
+
+    /// The layers and rules of the nearest directory with a configuration of its own
+    fn local_in(&self, directory: &Path) -> Option<Local> {
+        let local_patterns = self.local_patterns.read().unwrap();
+        directory
+            .ancestors()
+            .find_map(|directory| local_patterns.get(directory).cloned())
+    }
+
+    /// The rules for the entries of a directory
+    pub fn patterns_in(&self, directory: &Path) -> Arc<config::Patterns> {
+        self.local_in(directory)
+            .map_or_else(|| self.patterns.clone(), |(_, patterns)| patterns)
+    }
+
+    /// The rules for an entry, the ones of the directory it is in
+    pub fn patterns_of(&self, path: &Path) -> Arc<config::Patterns> {
+        self.patterns_in(path.parent().unwrap_or(path))
+    }
+
+    /// Put configuration files over the rules in a directory, for everything under it.
+    /// When one is broken, the rules above are kept for it.
+    pub fn add_local(&self, directory: &Path, config_paths: &[PathBuf]) {
+        if config_paths.is_empty() {
+            return;
+        }
+        let layers = self
+            .local_in(directory)
+            .map_or_else(|| self.layers.clone(), |(layers, _)| layers);
+        let mut merged = (*layers).clone();
+        for config_path in config_paths {
+            merged = match config::merge_file(merged, config_path) {
+                Ok(merged) => merged,
+                Err(err) => {
+                    eprintln!("Error: {}, the rules above {:?} are used", err, directory);
+                    return;
+                }
+            };
+        }
+        match config::compile_layers(&merged) {
+            Ok(patterns) => {
+                for config_path in config_paths {
+                    self.tried(
+                        directory,
+                        "configuration",
+                        true,
+                        format_args!("{:?}", config_path),
+                    );
+                }
+                if self.is_debug() {
+                    println!(
+                        "Rules of {:?} put over the ones in {:?}",
+                        config_paths, directory
+                    );
+                }
+                self.local_patterns
+                    .write()
+                    .unwrap()
+                    .insert(directory.to_owned(), (Arc::new(merged), Arc::new(patterns)));
+            }
+            Err(errors) => {
+                for err in errors {
+                    eprintln!("Error: Invalid {}", err);
+                }
+                eprintln!(
+                    "Call `maid -c {} config check` to find them, the rules above {:?} are used",
+                    config_paths[config_paths.len() - 1].display(),
+                    directory
+                );
+            }
+        }
+    }
+
+    pub async fn new(config: MaidConfig) -> Result<Self, Box<dyn Error>> {
+        let (layers, patterns) = config::load_patterns(&config.config_paths());
+
+        let store = if config.use_mongodb || config.save {
+            Some(
+                store::open(config.store_url())
+                    .await
+                    .map_err(|e| e as Box<dyn Error>)?,
+            )
         } else {
-            config::load_patterns(PathBuf::from(
-                dirs::home_dir().unwrap().join(".maidsweep.yaml"),
-            ))
+            None
         };
 
-        let mongodb = if config.use_mongodb || config.save {
-            Some(MongoDBContext::new(&config).await.unwrap())
+        let query = Query::from_config(&config, &patterns);
+        let duplicates = if config.dedupe.is_some() || query.has_tag(DUPLICATE_TAG) {
+            Some(Mutex::new(vec![]))
         } else {
             None
         };
 
-        MaidContext {
+        let jobs = config.jobs.get();
+        Ok(MaidContext {
+            jobs: Semaphore::new(jobs),
+            walkers: Arc::new(Semaphore::new(jobs)),
+            query,
             config,
-            mongodb,
-            patterns,
-        }
+            store,
+            patterns: Arc::new(patterns),
+            layers: Arc::new(layers),
+            local_patterns: RwLock::new(HashMap::new()),
+            journal: Journal::new(),
+            summary: RunSummary::default(),
+            exec_batch: Mutex::new(BTreeMap::new()),
+            duplicates,
+            trace: None,
+        })
     }
 }
 
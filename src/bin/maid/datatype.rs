@@ -1,12 +1,211 @@
 use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What an entry is, links are not followed
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    #[default]
+    File,
+    Dir,
+    Symlink,
+    /// Devices, sockets, pipes
+    Other,
+    /// Sibling files with names in a row, like `01.jpg`, `02.jpg`, kept together in a folder
+    Series,
+    /// The volumes of a split archive, like `movie.part1.rar`, `movie.part2.rar`, kept side by side
+    Volumes,
+}
+
+impl EntryKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Other => "other",
+            EntryKind::Series => "series",
+            EntryKind::Volumes => "volumes",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            EntryKind::File,
+            EntryKind::Dir,
+            EntryKind::Symlink,
+            EntryKind::Other,
+            EntryKind::Series,
+            EntryKind::Volumes,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
+}
+
+/// The metadata of an entry, times are in seconds since the Unix epoch
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct EntryMetadata {
+    pub kind: EntryKind,
+    /// For directories, the total size of the files in it
+    pub size: u64,
+    /// For directories, the newest among everything in it
+    pub last_modified: u64,
+    /// For directories, the newest among the files in it
+    pub last_accessed: u64,
+    /// When the metadata last changed, or when it was created where that is not known
+    pub last_changed: u64,
+    pub inode: u64,
+    pub device: u64,
+    /// The BLAKE3 hash of the content in hex, for the files that were looked at for duplicates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+fn seconds(time: std::io::Result<SystemTime>) -> u64 {
+    time.ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+impl From<&fs::Metadata> for EntryMetadata {
+    fn from(metadata: &fs::Metadata) -> Self {
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            EntryKind::Symlink
+        } else if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        };
+
+        #[cfg(unix)]
+        let (last_changed, inode, device) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                metadata.ctime().max(0) as u64,
+                metadata.ino(),
+                metadata.dev(),
+            )
+        };
+        #[cfg(not(unix))]
+        let (last_changed, inode, device) = (seconds(metadata.created()), 0, 0);
+
+        EntryMetadata {
+            kind,
+            size: metadata.len(),
+            last_modified: seconds(metadata.modified()),
+            last_accessed: seconds(metadata.accessed()),
+            last_changed,
+            inode,
+            device,
+            hash: None,
+        }
+    }
+}
+
+impl EntryMetadata {
+    /// The metadata of a group of files, their total size and their newest times
+    pub fn combined<'a, I>(kind: EntryKind, members: I) -> Self
+    where
+        I: IntoIterator<Item = &'a EntryMetadata>,
+    {
+        members.into_iter().fold(
+            EntryMetadata {
+                kind,
+                ..Default::default()
+            },
+            |combined, member| EntryMetadata {
+                size: combined.size + member.size,
+                last_modified: combined.last_modified.max(member.last_modified),
+                last_accessed: combined.last_accessed.max(member.last_accessed),
+                last_changed: combined.last_changed.max(member.last_changed),
+                ..combined
+            },
+        )
+    }
+}
 
 /// Define a type that models our metadata.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct FileMetaCompat {
     pub path: PathBuf,
     pub tags: Vec<String>,
-    pub last_modified: u64,
+    #[serde(flatten)]
+    pub metadata: EntryMetadata,
+    /// The files of a group entry like a series or a split archive, its path may not be on disk
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<PathBuf>,
+}
+
+/// Why an entry got its tags
+#[derive(Clone, Debug)]
+pub enum MatchReason {
+    /// The extension is listed under the tags
+    Extension {
+        extension: String,
+        tags: Vec<String>,
+    },
+    /// The content starts with the magic number of a format
+    Signature(String),
+    /// The file name matches a special file pattern, giving the tags
+    Filename { pattern: String, tags: Vec<String> },
+    /// The directory contains a marker file, matched by a pattern of a typical files rule
+    TypicalFile {
+        rule: String,
+        pattern: String,
+        marker: PathBuf,
+    },
+    /// A garbage rule matched, `empty`, `broken link`, `hex name` or a name pattern
+    Garbage(String),
+    /// The content is the same as the copy that is kept
+    Duplicate(PathBuf),
+    /// The siblings have names in a row by a series rule
+    Series(String),
+    /// The files are the volumes of one archive
+    Volumes(usize),
+    /// Nothing matched, so it is tagged as misc
+    Fallback,
+    /// The entry is within the limits of an age tag
+    Age(String),
+    /// The tags are read from the database
+    Database,
+}
+
+impl Display for MatchReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchReason::Extension { extension, tags } => {
+                write!(f, "extension `{}` of `{}`", extension, tags.join("`, `"))
+            }
+            MatchReason::Signature(name) => write!(f, "signature `{}`", name),
+            MatchReason::Filename { pattern, tags } => {
+                write!(f, "filename `{}` of `{}`", pattern, tags.join("`, `"))
+            }
+            MatchReason::TypicalFile {
+                rule,
+                pattern,
+                marker,
+            } => write!(
+                f,
+                "typical file {:?} by `{}` of `{}`",
+                marker, pattern, rule
+            ),
+            MatchReason::Garbage(rule) => write!(f, "garbage rule `{}`", rule),
+            MatchReason::Duplicate(original) => write!(f, "duplicate of {:?}", original),
+            MatchReason::Series(rule) => write!(f, "series `{}`", rule),
+            MatchReason::Volumes(count) => write!(f, "{} volumes", count),
+            MatchReason::Fallback => write!(f, "no rule"),
+            MatchReason::Age(tag) => write!(f, "age tag `{}`", tag),
+            MatchReason::Database => write!(f, "database"),
+        }
+    }
 }
 
 /// Define how it is passed around
@@ -14,15 +213,30 @@ pub struct FileMetaCompat {
 pub struct FileMeta {
     pub path: PathBuf,
     pub tags: Option<Vec<String>>,
-    pub last_modified: Option<u64>,
+    pub metadata: Option<EntryMetadata>,
+    pub reasons: Vec<MatchReason>,
+    /// The files of a group entry, empty for the others
+    pub members: Vec<PathBuf>,
+}
+
+impl FileMeta {
+    /// The files on disk, the members of a group entry or the entry itself
+    pub fn files(&self) -> Vec<PathBuf> {
+        if self.members.is_empty() {
+            vec![self.path.clone()]
+        } else {
+            self.members.clone()
+        }
+    }
 }
 
-impl Into<FileMetaCompat> for FileMeta {
-    fn into(self) -> FileMetaCompat {
+impl From<FileMeta> for FileMetaCompat {
+    fn from(file_meta: FileMeta) -> Self {
         FileMetaCompat {
-            path: self.path,
-            tags: self.tags.unwrap_or(vec![]),
-            last_modified: self.last_modified.unwrap_or(0),
+            path: file_meta.path,
+            tags: file_meta.tags.unwrap_or_default(),
+            metadata: file_meta.metadata.unwrap_or_default(),
+            members: file_meta.members,
         }
     }
 }
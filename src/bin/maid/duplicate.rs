@@ -0,0 +1,299 @@
+use crate::config::DedupePolicy;
+use crate::datatype::{EntryMetadata, FileMeta};
+use crate::fileop::{self, FileOpError};
+use futures::stream::{self, StreamExt};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+/// What the copies of a file are tagged with, the one that is kept is not
+pub const DUPLICATE_TAG: &str = "duplicate";
+
+/// How much of a file is hashed first, so most files of the same size are told apart cheaply
+const PARTIAL_SIZE: u64 = 64 * 1024;
+
+/// Bring the metadata up to date with the disk, as entries from the store may be old.
+/// A saved hash is dropped if the file has changed since.
+fn refresh(file_meta: &mut FileMeta) -> bool {
+    let current = match fs::symlink_metadata(&file_meta.path) {
+        Ok(metadata) => EntryMetadata::from(&metadata),
+        Err(_) => return false,
+    };
+    let metadata = file_meta.metadata.get_or_insert_with(Default::default);
+    let hash = metadata.hash.take().filter(|_| {
+        metadata.size == current.size && metadata.last_modified == current.last_modified
+    });
+    *metadata = EntryMetadata { hash, ..current };
+    metadata.size > 0
+}
+
+fn size_of(file_meta: &FileMeta) -> u64 {
+    file_meta
+        .metadata
+        .as_ref()
+        .map_or(0, |metadata| metadata.size)
+}
+
+/// Hash the entries on blocking threads, as many at once as there are jobs.
+/// The ones that cannot be read are put aside.
+async fn hash_all<F>(
+    entries: Vec<FileMeta>,
+    jobs: usize,
+    hash: F,
+    alone: &mut Vec<FileMeta>,
+) -> Vec<(String, FileMeta)>
+where
+    F: Fn(&FileMeta) -> Result<String, FileOpError> + Clone + Send + 'static,
+{
+    let results: Vec<_> = stream::iter(entries)
+        .map(|file_meta| {
+            let hash = hash.clone();
+            tokio::task::spawn_blocking(move || (hash(&file_meta), file_meta))
+        })
+        .buffer_unordered(jobs)
+        .collect()
+        .await;
+    let mut hashed = vec![];
+    for result in results {
+        match result {
+            Ok((Ok(hash), file_meta)) => hashed.push((hash, file_meta)),
+            Ok((Err(e), file_meta)) => {
+                eprintln!("Error: {}", e);
+                alone.push(file_meta);
+            }
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+    hashed
+}
+
+/// Split the entries by a key, the groups of one are put aside
+fn split_by<K, I>(entries: I, alone: &mut Vec<FileMeta>) -> Vec<Vec<FileMeta>>
+where
+    K: Eq + std::hash::Hash,
+    I: IntoIterator<Item = (K, FileMeta)>,
+{
+    let mut groups: HashMap<K, Vec<FileMeta>> = HashMap::new();
+    for (key, file_meta) in entries {
+        groups.entry(key).or_default().push(file_meta);
+    }
+    groups
+        .into_values()
+        .filter_map(|mut group| {
+            if group.len() > 1 {
+                Some(group)
+            } else {
+                alone.append(&mut group);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Group the files with the same content, by size, then by the hash of their first bytes,
+/// then by the hash of all of it. Every entry is returned, the ones without copies alone.
+/// The full hashes are set in the metadata, saved ones are used if the file has not changed.
+pub async fn group(entries: Vec<FileMeta>, jobs: usize) -> Vec<Vec<FileMeta>> {
+    let (entries, mut alone) = tokio::task::spawn_blocking(move || {
+        let mut entries = entries;
+        let mut alone = vec![];
+        entries.retain_mut(|file_meta| {
+            if refresh(file_meta) {
+                true
+            } else {
+                alone.push(file_meta.clone());
+                false
+            }
+        });
+        (entries, alone)
+    })
+    .await
+    .unwrap_or_default();
+
+    // links to a file are not copies of it, only one of them is hashed
+    let mut seen = HashSet::new();
+    let mut linked = vec![];
+    let entries: Vec<FileMeta> = entries
+        .into_iter()
+        .filter(|file_meta| {
+            let metadata = file_meta.metadata.as_ref().unwrap();
+            if metadata.inode != 0 && !seen.insert((metadata.device, metadata.inode)) {
+                linked.push(file_meta.clone());
+                false
+            } else {
+                true
+            }
+        })
+        .collect();
+
+    let by_size = split_by(
+        entries
+            .into_iter()
+            .map(|file_meta| (size_of(&file_meta), file_meta)),
+        &mut alone,
+    );
+
+    let mut candidates = vec![];
+    for group in by_size {
+        let all_hashed = group.iter().all(|file_meta| {
+            file_meta
+                .metadata
+                .as_ref()
+                .is_some_and(|metadata| metadata.hash.is_some())
+        });
+        if all_hashed || size_of(&group[0]) <= PARTIAL_SIZE {
+            candidates.push(group);
+            continue;
+        }
+        let partial = hash_all(
+            group,
+            jobs,
+            |file_meta| {
+                fileop::hash_head(&file_meta.path, PARTIAL_SIZE)
+                    .map(|hash| hash.to_hex().to_string())
+            },
+            &mut alone,
+        )
+        .await;
+        candidates.extend(split_by(partial, &mut alone));
+    }
+
+    let mut groups = vec![];
+    for group in candidates {
+        let full = hash_all(
+            group,
+            jobs,
+            |file_meta| match file_meta.metadata.as_ref().and_then(|m| m.hash.clone()) {
+                Some(hash) => Ok(hash),
+                None => fileop::hash_path(&file_meta.path).map(|hash| hash.to_hex().to_string()),
+            },
+            &mut alone,
+        )
+        .await
+        .into_iter()
+        .map(|(hash, mut file_meta)| {
+            if let Some(ref mut metadata) = file_meta.metadata {
+                metadata.hash = Some(hash.clone());
+            }
+            (hash, file_meta)
+        });
+        groups.extend(split_by(full, &mut alone));
+    }
+
+    // the other links are the same file, not copies, so they are never tagged,
+    // they only take the hash of the file they link to
+    for mut file_meta in linked {
+        let key = |metadata: &EntryMetadata| (metadata.device, metadata.inode);
+        let inode = key(file_meta.metadata.as_ref().unwrap());
+        let hash = groups
+            .iter()
+            .flatten()
+            .find(|other| other.metadata.as_ref().map(key) == Some(inode))
+            .and_then(|other| other.metadata.as_ref()?.hash.clone());
+        if let (Some(hash), Some(ref mut metadata)) = (hash, file_meta.metadata.as_mut()) {
+            metadata.hash = Some(hash);
+        }
+        alone.push(file_meta);
+    }
+
+    groups.extend(alone.into_iter().map(|file_meta| vec![file_meta]));
+    groups
+}
+
+/// Put the copy to keep first
+pub fn sort_by_policy(policy: DedupePolicy, group: &mut [FileMeta]) {
+    let modified = |file_meta: &FileMeta| {
+        file_meta
+            .metadata
+            .as_ref()
+            .map_or(0, |metadata| metadata.last_modified)
+    };
+    match policy {
+        DedupePolicy::KeepOldest | DedupePolicy::Hardlink => {
+            group.sort_by_key(|file_meta| (modified(file_meta), file_meta.path.clone()))
+        }
+        DedupePolicy::KeepNewest => {
+            group.sort_by_key(|file_meta| (Reverse(modified(file_meta)), file_meta.path.clone()))
+        }
+        DedupePolicy::KeepShortestPath => group.sort_by_key(|file_meta| {
+            (
+                file_meta.path.as_os_str().len(),
+                modified(file_meta),
+                file_meta.path.clone(),
+            )
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn entry(path: &Path) -> FileMeta {
+        FileMeta {
+            path: path.to_owned(),
+            tags: None,
+            metadata: None,
+            reasons: vec![],
+            members: vec![],
+        }
+    }
+
+    fn paths(group: &[FileMeta]) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = group.iter().map(|f| f.path.clone()).collect();
+        paths.sort();
+        paths
+    }
+
+    #[tokio::test]
+    async fn hard_links_are_not_copies() {
+        let directory = std::env::temp_dir().join(format!("maid-duplicate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let file = |name: &str| directory.join(name);
+        fs::write(file("a.txt"), "same").unwrap();
+        fs::write(file("b.txt"), "same").unwrap();
+        fs::hard_link(file("a.txt"), file("link.txt")).unwrap();
+        fs::write(file("c.txt"), "other").unwrap();
+        fs::write(file("empty.txt"), "").unwrap();
+
+        let names = ["a.txt", "link.txt", "b.txt", "c.txt", "empty.txt"];
+        let entries = names.iter().map(|name| entry(&file(name))).collect();
+        let groups = group(entries, 2).await;
+        fs::remove_dir_all(&directory).unwrap();
+
+        let copies: Vec<&Vec<FileMeta>> = groups.iter().filter(|g| g.len() > 1).collect();
+        assert_eq!(copies.len(), 1);
+        assert_eq!(paths(copies[0]), [file("a.txt"), file("b.txt")]);
+        // every entry is returned, the link with the hash of the file it links to
+        assert_eq!(groups.iter().map(Vec::len).sum::<usize>(), names.len());
+        let hash_of = |path: PathBuf| {
+            groups
+                .iter()
+                .flatten()
+                .find(|f| f.path == path)
+                .and_then(|f| f.metadata.as_ref()?.hash.clone())
+        };
+        assert!(hash_of(file("a.txt")).is_some());
+        assert_eq!(hash_of(file("link.txt")), hash_of(file("a.txt")));
+    }
+
+    #[test]
+    fn the_copy_kept_goes_first() {
+        let dated = |path: &str, modified: u64| FileMeta {
+            metadata: Some(EntryMetadata {
+                last_modified: modified,
+                ..Default::default()
+            }),
+            ..entry(Path::new(path))
+        };
+        let mut group = vec![dated("/a/long/name", 1), dated("/b", 3), dated("/c/d", 2)];
+        sort_by_policy(DedupePolicy::KeepOldest, &mut group);
+        assert_eq!(group[0].path, Path::new("/a/long/name"));
+        sort_by_policy(DedupePolicy::KeepNewest, &mut group);
+        assert_eq!(group[0].path, Path::new("/b"));
+        sort_by_policy(DedupePolicy::KeepShortestPath, &mut group);
+        assert_eq!(group[0].path, Path::new("/b"));
+    }
+}
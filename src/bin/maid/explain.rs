@@ -0,0 +1,181 @@
+use crate::context::MaidContext;
+use crate::datatype::{EntryKind, EntryMetadata, FileMeta};
+use crate::layer;
+use crate::processor::{Directory, ProcessError, Processor};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A rule the walk tried, and whether it matched
+struct Step {
+    /// The entry it was tried on
+    path: PathBuf,
+    section: &'static str,
+    matched: bool,
+    rule: String,
+}
+
+/// What the walk does on the way to the entry explained, instead of sweeping
+pub struct Trace {
+    target: PathBuf,
+    /// The group the entry is in and its files, the rules tried on them are for it too
+    group: Mutex<Vec<PathBuf>>,
+    steps: Mutex<Vec<Step>>,
+    /// The entry tagged on the way, the one explained, its group or a directory it is in
+    tagged: Mutex<Option<FileMeta>>,
+}
+
+impl Trace {
+    pub fn new(target: PathBuf) -> Self {
+        Trace {
+            target,
+            group: Mutex::new(vec![]),
+            steps: Mutex::new(vec![]),
+            tagged: Mutex::new(None),
+        }
+    }
+
+    /// Whether an entry is on the way to the one explained
+    pub fn follows(&self, path: &Path) -> bool {
+        self.target.starts_with(path) || self.group.lock().unwrap().iter().any(|file| file == path)
+    }
+
+    /// Take a group of files as the entry, if it is one of them
+    pub fn grouped(&self, path: &Path, members: &[PathBuf]) {
+        if members.contains(&self.target) {
+            let mut group = self.group.lock().unwrap();
+            group.push(path.to_owned());
+            group.extend(members.iter().cloned());
+        }
+    }
+
+    pub fn tried(&self, path: &Path, section: &'static str, matched: bool, rule: impl Display) {
+        if self.follows(path) {
+            self.steps.lock().unwrap().push(Step {
+                path: path.to_owned(),
+                section,
+                matched,
+                rule: rule.to_string(),
+            });
+        }
+    }
+
+    pub fn tagged(&self, file_meta: FileMeta) {
+        self.tagged.lock().unwrap().get_or_insert(file_meta);
+    }
+
+    /// Print the rules tried, under their section and the entry they were tried on
+    fn print(&self) {
+        let mut heading = None;
+        for step in self.steps.lock().unwrap().iter() {
+            if heading != Some((&step.path, step.section)) {
+                if step.path == self.target {
+                    println!("{}:", step.section);
+                } else {
+                    println!("{} of {:?}:", step.section, step.path);
+                }
+                heading = Some((&step.path, step.section));
+            }
+            println!("  {} {}", if step.matched { "+" } else { "-" }, step.rule);
+        }
+    }
+}
+
+/// The innermost scanned directory the entry is in, below it
+async fn scanned_root(roots: Vec<PathBuf>, path: &Path) -> Option<PathBuf> {
+    let mut found: Option<PathBuf> = None;
+    for root in roots {
+        let root = match tokio::fs::canonicalize(&root).await {
+            Ok(root) => root,
+            Err(_) => continue,
+        };
+        if path != root
+            && path.starts_with(&root)
+            && found.as_ref().is_none_or(|found| root.starts_with(found))
+        {
+            found = Some(root);
+        }
+    }
+    found
+}
+
+/// Print how an entry is tagged when the sweep it is in is walked, with the rules the walk
+/// tries on the way, in the order it tries them
+pub async fn explain(mut context: MaidContext, path: &Path) -> Result<(), ProcessError> {
+    let entry_metadata = tokio::fs::symlink_metadata(path)
+        .await
+        .map(|metadata| EntryMetadata::from(&metadata))
+        .map_err(|e| format!("Cannot read {:?}: {}", path, e))?;
+    // a link is explained, not what it points to
+    let path = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => tokio::fs::canonicalize(parent.join("."))
+            .await
+            .map_err(|e| format!("Cannot find {:?}: {}", parent, e))?
+            .join(name),
+        _ => tokio::fs::canonicalize(path)
+            .await
+            .map_err(|e| format!("Cannot find {:?}: {}", path, e))?,
+    };
+    println!("{:?} ({})", path, entry_metadata.kind.name());
+
+    // the entries are only tagged, with every copy seen or not
+    context.trace = Some(Trace::new(path.clone()));
+    context.duplicates = None;
+    let context = Arc::new(context);
+
+    // walked from the root of the sweep it is in, with the configurations of the
+    // directories above, so every directory on the way is tried as well
+    let root = scanned_root(context.get_config().roots(), &path)
+        .await
+        .unwrap_or_else(|| path.parent().unwrap_or(&path).to_owned());
+    context.add_local(&root, &layer::ancestor_configs(&root));
+    Directory {}
+        .process(
+            context.clone(),
+            FileMeta {
+                path: root,
+                tags: None,
+                metadata: None,
+                reasons: vec![],
+                members: vec![],
+            },
+        )
+        .await?;
+
+    let trace = context.trace.as_ref().unwrap();
+    trace.print();
+    let tagged = match trace.tagged.lock().unwrap().take() {
+        Some(tagged) => tagged,
+        None if entry_metadata.kind == EntryKind::Dir => {
+            println!("Not tagged, the entries in it are tagged on their own");
+            return Ok(());
+        }
+        None => {
+            println!("Not tagged, hidden entries are only swept with --hidden");
+            return Ok(());
+        }
+    };
+    if tagged.path != path && path.starts_with(&tagged.path) {
+        println!(
+            "{:?} is tagged as a whole, a sweep stops there and never reaches the entry",
+            tagged.path
+        );
+    } else if !tagged.members.is_empty() {
+        println!(
+            "Tagged as one entry {:?} with {} files",
+            tagged.path,
+            tagged.members.len()
+        );
+    }
+    println!(
+        "tags: {}; matched: {}",
+        tagged.tags.unwrap_or_default().join(", "),
+        tagged
+            .reasons
+            .iter()
+            .map(|reason| reason.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Ok(())
+}
@@ -0,0 +1,390 @@
+use crate::datatype::{EntryKind, EntryMetadata};
+use filetime::FileTime;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A failed file operation, and the file it failed on
+#[derive(Debug)]
+pub struct FileOpError {
+    pub operation: &'static str,
+    pub path: PathBuf,
+    pub source: io::Error,
+}
+
+impl Display for FileOpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to {} {:?}: {}",
+            self.operation, self.path, self.source
+        )
+    }
+}
+
+impl Error for FileOpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn wrap(operation: &'static str, path: &Path) -> impl FnOnce(io::Error) -> FileOpError {
+    let path = path.to_owned();
+    move |source| FileOpError {
+        operation,
+        path,
+        source,
+    }
+}
+
+/// Copy the permissions and the access and modification times
+fn copy_attributes(metadata: &fs::Metadata, to: &Path) -> Result<(), FileOpError> {
+    fs::set_permissions(to, metadata.permissions()).map_err(wrap("set permissions of", to))?;
+    filetime::set_file_times(
+        to,
+        FileTime::from_last_access_time(metadata),
+        FileTime::from_last_modification_time(metadata),
+    )
+    .map_err(wrap("set times of", to))
+}
+
+#[cfg(unix)]
+fn copy_symlink(from: &Path, to: &Path) -> Result<(), FileOpError> {
+    let target = fs::read_link(from).map_err(wrap("read link", from))?;
+    std::os::unix::fs::symlink(target, to).map_err(wrap("create link", to))
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(from: &Path, to: &Path) -> Result<(), FileOpError> {
+    // links on other platforms need privileges to create, so copy what they point to
+    copy_file(from, to)
+}
+
+/// A new file to write to, never one that is there already
+fn create_new(path: &Path) -> Result<fs::File, FileOpError> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(wrap("create", path))
+}
+
+/// Copy the content of a file to a new one
+fn copy_file(from: &Path, to: &Path) -> Result<(), FileOpError> {
+    let mut copy = create_new(to)?;
+    let result = fs::File::open(from)
+        .and_then(|mut source| io::copy(&mut source, &mut copy))
+        .map_err(wrap("copy", from));
+    if result.is_err() {
+        let _ = fs::remove_file(to);
+    }
+    result.map(|_| ())
+}
+
+/// Copy a file or a directory recursively to `to`, which is the path of the copy.
+/// Fails with `AlreadyExists` if something is there, and leaves no partial copy behind.
+pub fn copy_path(from: &Path, to: &Path) -> Result<(), FileOpError> {
+    let metadata = fs::symlink_metadata(from).map_err(wrap("read", from))?;
+    let file_type = metadata.file_type();
+
+    if file_type.is_symlink() {
+        return copy_symlink(from, to);
+    }
+
+    if file_type.is_dir() {
+        // claimed first, so nothing is ever copied into what was there
+        fs::create_dir(to).map_err(wrap("create directory", to))?;
+        let result = fs::read_dir(from)
+            .map_err(wrap("read directory", from))
+            .and_then(|entries| {
+                for entry in entries {
+                    let entry = entry.map_err(wrap("read directory", from))?;
+                    copy_path(&entry.path(), &to.join(entry.file_name()))?;
+                }
+                Ok(())
+            });
+        if let Err(e) = result {
+            let _ = remove_path(to);
+            return Err(e);
+        }
+    } else {
+        copy_file(from, to)?;
+    }
+
+    // set the directory times last, as filling it changes them
+    copy_attributes(&metadata, to)
+}
+
+/// Remove a file or a directory recursively, links are removed but not followed
+pub fn remove_path(path: &Path) -> Result<(), FileOpError> {
+    let metadata = fs::symlink_metadata(path).map_err(wrap("read", path))?;
+    if metadata.is_dir() {
+        fs::remove_dir_all(path).map_err(wrap("remove", path))
+    } else {
+        fs::remove_file(path).map_err(wrap("remove", path))
+    }
+}
+
+/// Claim a path for an entry to be moved to, with an empty file or directory of its own,
+/// failing with `AlreadyExists` if something is there
+fn reserve(path: &Path, is_dir: bool) -> Result<(), FileOpError> {
+    if is_dir {
+        fs::create_dir(path).map_err(wrap("create directory", path))
+    } else {
+        create_new(path).map(|_| ())
+    }
+}
+
+/// Move a file or a directory to `to`, which is its new path.
+/// Fails with `AlreadyExists` if something is there, as the path is claimed before
+/// the entry replaces the claim in one step.
+/// Falls back to copying and removing when they are on different filesystems.
+pub fn move_path(from: &Path, to: &Path) -> Result<(), FileOpError> {
+    let metadata = fs::symlink_metadata(from).map_err(wrap("read", from))?;
+    reserve(to, metadata.is_dir())?;
+    match fs::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = remove_path(to);
+            if e.kind() != io::ErrorKind::CrossesDevices {
+                return Err(wrap("move", from)(e));
+            }
+            copy_path(from, to)?;
+            remove_path(from)
+        }
+    }
+}
+
+/// Hash the content of a file, or of every file in a directory along with their names
+pub fn hash_path(path: &Path) -> Result<blake3::Hash, FileOpError> {
+    let metadata = fs::symlink_metadata(path).map_err(wrap("read", path))?;
+    let mut hasher = blake3::Hasher::new();
+    if metadata.is_dir() {
+        let mut entries = fs::read_dir(path)
+            .map_err(wrap("read directory", path))?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(wrap("read directory", path))?;
+        entries.sort();
+        for name in entries {
+            hasher.update(name.to_string_lossy().as_bytes());
+            hasher.update(hash_path(&path.join(&name))?.as_bytes());
+        }
+    } else if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path).map_err(wrap("read link", path))?;
+        hasher.update(target.to_string_lossy().as_bytes());
+    } else {
+        let file = fs::File::open(path).map_err(wrap("read", path))?;
+        hasher.update_reader(file).map_err(wrap("read", path))?;
+    }
+    Ok(hasher.finalize())
+}
+
+/// Hash the first bytes of a file, to tell apart files of the same size cheaply
+pub fn hash_head(path: &Path, length: u64) -> Result<blake3::Hash, FileOpError> {
+    let file = fs::File::open(path).map_err(wrap("read", path))?;
+    let mut hasher = blake3::Hasher::new();
+    hasher
+        .update_reader(io::Read::take(file, length))
+        .map_err(wrap("read", path))?;
+    Ok(hasher.finalize())
+}
+
+/// Put a new file in place of `path` in one step, so it is never missing.
+/// The new file is made next to it by `make`, and removed if that fails.
+fn replace_with<F>(path: &Path, make: F) -> Result<(), FileOpError>
+where
+    F: FnOnce(&Path) -> Result<(), FileOpError>,
+{
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temporary = path.with_file_name(format!(".{}.maid-{}", name, std::process::id()));
+    // left over from a run that was stopped
+    let _ = fs::remove_file(&temporary);
+    if let Err(e) = make(&temporary) {
+        let _ = fs::remove_file(&temporary);
+        return Err(e);
+    }
+    fs::rename(&temporary, path).map_err(|e| {
+        let _ = fs::remove_file(&temporary);
+        wrap("replace", path)(e)
+    })
+}
+
+/// Replace a file with a hard link to another one with the same content
+pub fn hard_link_over(original: &Path, path: &Path) -> Result<(), FileOpError> {
+    replace_with(path, |temporary| {
+        fs::hard_link(original, temporary).map_err(wrap("link to", original))
+    })
+}
+
+/// Whether the two paths are links to the same file
+pub fn same_file(one: &Path, other: &Path) -> bool {
+    match (fs::symlink_metadata(one), fs::symlink_metadata(other)) {
+        (Ok(one), Ok(other)) => {
+            let (one, other) = (EntryMetadata::from(&one), EntryMetadata::from(&other));
+            one.inode != 0 && one.inode == other.inode && one.device == other.device
+        }
+        _ => false,
+    }
+}
+
+/// Replace a hard link with a copy of the file, so they are apart again
+pub fn copy_over(original: &Path, path: &Path) -> Result<(), FileOpError> {
+    replace_with(path, |temporary| copy_path(original, temporary))
+}
+
+/// Add up the sizes of the files in a directory and keep the newest times,
+/// links are not followed and what cannot be read is left out
+fn summarize_dir(path: &Path, summary: &mut EntryMetadata) {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let child = match entry.metadata() {
+            Ok(metadata) => EntryMetadata::from(&metadata),
+            Err(_) => continue,
+        };
+        summary.last_modified = summary.last_modified.max(child.last_modified);
+        if child.kind == EntryKind::Dir {
+            // walking a directory touches its access time, so only files count for that
+            summarize_dir(&entry.path(), summary);
+        } else {
+            summary.size += child.size;
+            summary.last_accessed = summary.last_accessed.max(child.last_accessed);
+        }
+    }
+}
+
+/// Fill in the metadata of a directory from what is in it, the total size of the files
+/// and the times of the newest of them. The metadata of other entries is complete already.
+pub fn complete_metadata(path: &Path, mut entry_metadata: EntryMetadata) -> EntryMetadata {
+    if entry_metadata.kind == EntryKind::Dir {
+        let mut summary = EntryMetadata::default();
+        summarize_dir(path, &mut summary);
+        entry_metadata.size = summary.size;
+        if summary.last_modified > 0 {
+            entry_metadata.last_modified = summary.last_modified;
+        }
+        if summary.last_accessed > 0 {
+            entry_metadata.last_accessed = summary.last_accessed;
+        }
+    }
+    entry_metadata
+}
+
+/// The metadata of an entry without following links, completed if it is a directory
+pub fn entry_metadata(path: &Path) -> Result<EntryMetadata, FileOpError> {
+    let metadata = fs::symlink_metadata(path).map_err(wrap("read", path))?;
+    Ok(complete_metadata(path, EntryMetadata::from(&metadata)))
+}
+
+/// The absolute path with the links in its parents resolved, a link itself is not followed
+pub fn canonical_path(path: &Path) -> Result<PathBuf, FileOpError> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => Ok(parent
+            .canonicalize()
+            .map_err(wrap("resolve", parent))?
+            .join(name)),
+        (_, Some(name)) => Ok(std::env::current_dir()
+            .map_err(wrap("resolve", path))?
+            .join(name)),
+        _ => path.canonicalize().map_err(wrap("resolve", path)),
+    }
+}
+
+/// The path with a numeric suffix, `name_1.ext` for `name.ext`
+pub fn numbered(path: &Path, number: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for a test
+    fn scratch(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("maid-fileop-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn is_taken(result: Result<(), FileOpError>) -> bool {
+        matches!(result, Err(e) if e.source.kind() == io::ErrorKind::AlreadyExists)
+    }
+
+    #[test]
+    fn moves_never_replace() {
+        let directory = scratch("move");
+        let (from, to) = (directory.join("a.txt"), directory.join("b.txt"));
+        fs::write(&from, "new").unwrap();
+        fs::write(&to, "old").unwrap();
+        assert!(is_taken(move_path(&from, &to)));
+        assert_eq!(fs::read_to_string(&from).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&to).unwrap(), "old");
+
+        let free = directory.join("c.txt");
+        move_path(&from, &free).unwrap();
+        assert!(fs::symlink_metadata(&from).is_err());
+        assert_eq!(fs::read_to_string(&free).unwrap(), "new");
+
+        // an empty directory is something too
+        let (folder, taken) = (directory.join("folder"), directory.join("taken"));
+        fs::create_dir(&folder).unwrap();
+        fs::write(folder.join("x"), "x").unwrap();
+        fs::create_dir(&taken).unwrap();
+        assert!(is_taken(move_path(&folder, &taken)));
+        assert!(is_taken(move_path(&free, &taken)));
+        assert!(folder.join("x").is_file());
+        move_path(&folder, &directory.join("moved")).unwrap();
+        assert!(directory.join("moved").join("x").is_file());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn copies_never_replace() {
+        let directory = scratch("copy");
+        let (from, to) = (directory.join("a.txt"), directory.join("b.txt"));
+        fs::write(&from, "new").unwrap();
+        fs::write(&to, "old").unwrap();
+        assert!(is_taken(copy_path(&from, &to)));
+        assert_eq!(fs::read_to_string(&to).unwrap(), "old");
+
+        let folder = directory.join("folder");
+        fs::create_dir_all(folder.join("inner")).unwrap();
+        fs::write(folder.join("inner").join("x"), "x").unwrap();
+        assert!(is_taken(copy_path(&folder, &to)));
+        copy_path(&folder, &directory.join("copy")).unwrap();
+        assert_eq!(
+            fs::read_to_string(directory.join("copy").join("inner").join("x")).unwrap(),
+            "x"
+        );
+        assert!(folder.join("inner").join("x").is_file());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn numbered_names() {
+        assert_eq!(
+            numbered(Path::new("/d/photo.jpg"), 1),
+            Path::new("/d/photo_1.jpg")
+        );
+        assert_eq!(
+            numbered(Path::new("/d/Makefile"), 12),
+            Path::new("/d/Makefile_12")
+        );
+        assert_eq!(
+            numbered(Path::new("/d/archive.tar.gz"), 2),
+            Path::new("/d/archive.tar_2.gz")
+        );
+    }
+}
@@ -0,0 +1,273 @@
+use crate::datatype::EntryMetadata;
+use chrono::{Local, NaiveDate, TimeZone};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+/// Units of `1y`, `6mo`, `2w` and so on, in seconds
+const AGE_UNITS: [(&str, u64); 7] = [
+    ("s", 1),
+    ("min", MINUTE),
+    ("h", HOUR),
+    ("d", DAY),
+    ("w", 7 * DAY),
+    ("mo", 30 * DAY),
+    ("y", 365 * DAY),
+];
+
+/// Units of `100M`, `1GiB` and so on, in bytes
+const SIZE_UNITS: [(&str, u64); 5] = [
+    ("", 1),
+    ("k", 1 << 10),
+    ("m", 1 << 20),
+    ("g", 1 << 30),
+    ("t", 1 << 40),
+];
+
+fn split_number(value: &str) -> Result<(u64, &str), String> {
+    let value = value.trim();
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let number = value[..end]
+        .parse()
+        .map_err(|_| format!("{:?} does not start with a number", value))?;
+    Ok((number, value[end..].trim()))
+}
+
+/// A number in a unit, within what the stores can keep as a signed 64-bit integer
+fn in_unit(number: u64, unit: u64, value: &str) -> Result<u64, String> {
+    number
+        .checked_mul(unit)
+        .filter(|total| *total <= i64::MAX as u64)
+        .ok_or_else(|| format!("{:?} is too large", value))
+}
+
+fn now() -> u64 {
+    Local::now().timestamp().max(0) as u64
+}
+
+/// An age like `1y` or `180d`, in seconds
+pub fn parse_age(value: &str) -> Result<u64, String> {
+    let (number, unit) = split_number(value)?;
+    AGE_UNITS
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .ok_or_else(|| {
+            format!(
+                "Unknown unit {:?} in {:?}, expected one of s, min, h, d, w, mo, y",
+                unit, value
+            )
+        })
+        .and_then(|(_, seconds)| in_unit(number, *seconds, value))
+}
+
+/// A point in time given as an age like `1y`, or a date like `2023-01-31`,
+/// in seconds since the Unix epoch
+pub fn parse_time(value: &str) -> Result<u64, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+        return Local
+            .from_local_datetime(&midnight)
+            .earliest()
+            .map(|time| time.timestamp().max(0) as u64)
+            .ok_or_else(|| format!("{:?} does not exist in the local time zone", value));
+    }
+    Ok(now().saturating_sub(parse_age(value)?))
+}
+
+/// A size like `100M` or `1GiB`, in bytes, the units are powers of 1024
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, unit) = split_number(value)?;
+    let unit = unit.to_ascii_lowercase();
+    let unit = unit
+        .strip_suffix("ib")
+        .or_else(|| unit.strip_suffix('b'))
+        .unwrap_or(&unit);
+    SIZE_UNITS
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .ok_or_else(|| {
+            format!(
+                "Unknown unit {:?} in {:?}, expected one of B, K, M, G, T",
+                unit, value
+            )
+        })
+        .and_then(|(_, bytes)| in_unit(number, *bytes, value))
+}
+
+/// A number in the metadata that can be compared
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    Size,
+    LastModified,
+    LastAccessed,
+}
+
+impl Field {
+    /// The name in the store
+    pub fn name(&self) -> &'static str {
+        match self {
+            Field::Size => "size",
+            Field::LastModified => "last_modified",
+            Field::LastAccessed => "last_accessed",
+        }
+    }
+
+    pub fn of(&self, metadata: &EntryMetadata) -> u64 {
+        match self {
+            Field::Size => metadata.size,
+            Field::LastModified => metadata.last_modified,
+            Field::LastAccessed => metadata.last_accessed,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+impl Comparison {
+    pub fn holds(&self, left: u64, right: u64) -> bool {
+        match self {
+            Comparison::Less => left < right,
+            Comparison::LessOrEqual => left <= right,
+            Comparison::Greater => left > right,
+            Comparison::GreaterOrEqual => left >= right,
+            Comparison::Equal => left == right,
+        }
+    }
+
+    /// The comparison with the sides swapped
+    pub fn reversed(&self) -> Self {
+        match self {
+            Comparison::Less => Comparison::Greater,
+            Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+            Comparison::Greater => Comparison::Less,
+            Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+            Comparison::Equal => Comparison::Equal,
+        }
+    }
+
+    pub fn sql_operator(&self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+            Comparison::Equal => "=",
+        }
+    }
+
+    pub fn mongo_operator(&self) -> &'static str {
+        match self {
+            Comparison::Less => "$lt",
+            Comparison::LessOrEqual => "$lte",
+            Comparison::Greater => "$gt",
+            Comparison::GreaterOrEqual => "$gte",
+            Comparison::Equal => "$eq",
+        }
+    }
+}
+
+/// A condition on the metadata, like `size > 1G`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bound {
+    pub field: Field,
+    pub comparison: Comparison,
+    pub value: u64,
+}
+
+impl Bound {
+    pub fn matches(&self, metadata: &EntryMetadata) -> bool {
+        self.comparison.holds(self.field.of(metadata), self.value)
+    }
+}
+
+/// The bounds for the limits that are given, as in --older-than and so on
+pub fn bounds(
+    older_than: Option<u64>,
+    newer_than: Option<u64>,
+    accessed_before: Option<u64>,
+    larger_than: Option<u64>,
+    smaller_than: Option<u64>,
+) -> Vec<Bound> {
+    [
+        (Field::LastModified, Comparison::Less, older_than),
+        (Field::LastModified, Comparison::GreaterOrEqual, newer_than),
+        (Field::LastAccessed, Comparison::Less, accessed_before),
+        (Field::Size, Comparison::Greater, larger_than),
+        (Field::Size, Comparison::Less, smaller_than),
+    ]
+    .into_iter()
+    .filter_map(|(field, comparison, value)| {
+        Some(Bound {
+            field,
+            comparison,
+            value: value?,
+        })
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_in_powers_of_1024() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512B"), Ok(512));
+        assert_eq!(parse_size("1k"), Ok(1024));
+        assert_eq!(parse_size(" 100M "), Ok(100 << 20));
+        assert_eq!(parse_size("1GiB"), Ok(1 << 30));
+        assert_eq!(parse_size("2tb"), Ok(2 << 40));
+        assert!(parse_size("1P").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("-1K").is_err());
+    }
+
+    #[test]
+    fn sizes_fit_in_a_signed_integer() {
+        assert_eq!(parse_size("8388607T"), Ok(8_388_607 << 40));
+        assert!(parse_size("8388608T").is_err());
+        assert_eq!(parse_size(&i64::MAX.to_string()), Ok(i64::MAX as u64));
+        assert!(parse_size(&(i64::MAX as u64 + 1).to_string()).is_err());
+        assert!(parse_size("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn ages_in_their_units() {
+        assert_eq!(parse_age("30s"), Ok(30));
+        assert_eq!(parse_age("5min"), Ok(5 * MINUTE));
+        assert_eq!(parse_age("180d"), Ok(180 * DAY));
+        assert_eq!(parse_age("6mo"), Ok(180 * DAY));
+        assert_eq!(parse_age("1y"), Ok(365 * DAY));
+        assert!(parse_age("1m").is_err());
+        assert!(parse_age("1").is_err());
+        assert!(parse_age("999999999999y").is_err());
+    }
+
+    #[test]
+    fn times_from_ages_or_dates() {
+        let before = now();
+        let time = parse_time("1d").unwrap();
+        assert!(time >= before - DAY && time <= now() - DAY);
+        // older than anything is the epoch, not an overflow
+        assert_eq!(parse_time("100000y"), Ok(0));
+
+        let date = parse_time("2023-01-31").unwrap();
+        let expected = Local
+            .with_ymd_and_hms(2023, 1, 31, 0, 0, 0)
+            .unwrap()
+            .timestamp();
+        assert_eq!(date, expected as u64);
+        assert!(parse_time("2023-02-30").is_err());
+    }
+}
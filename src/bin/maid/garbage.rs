@@ -0,0 +1,165 @@
+use crate::datatype::{EntryKind, EntryMetadata, MatchReason};
+use regex::Regex;
+use std::path::Path;
+
+/// What entries that are of no use are tagged with
+pub const GARBAGE_TAG: &str = "garbage";
+
+/// The rules telling an entry is of no use, each can be turned off in the config
+#[derive(Debug, Default)]
+pub struct GarbageRules {
+    /// Files of zero bytes
+    pub empty: bool,
+    /// Links pointing to nothing
+    pub broken_links: bool,
+    /// Names made of at least this many hex digits, like a UUID
+    pub hex_names: Option<usize>,
+    /// Names like `*.part`, the pattern is kept to tell why it matched
+    pub names: Vec<(String, Regex)>,
+}
+
+/// Turn a name pattern with `*` and `?` into a regex matching the whole name, ignoring case
+pub fn glob_to_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut regex = String::from("(?i)^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex)
+}
+
+/// The name up to the first dot, all hex digits and dashes, with enough of the former.
+/// It takes a letter too, as names of decimal digits only are usually ids or dates.
+fn is_hex_name(name: &str, min_length: usize) -> bool {
+    let stem = name.split('.').next().unwrap_or_default();
+    stem.chars().all(|c| c.is_ascii_hexdigit() || c == '-')
+        && stem.chars().filter(char::is_ascii_hexdigit).count() >= min_length
+        && stem.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+impl GarbageRules {
+    /// Why the entry is garbage, if it is
+    pub async fn check(&self, path: &Path, metadata: &EntryMetadata) -> Option<MatchReason> {
+        let name = path.file_name()?.to_string_lossy();
+        let reason = |rule: &str| Some(MatchReason::Garbage(rule.to_owned()));
+
+        if self.empty && metadata.kind == EntryKind::File && metadata.size == 0 {
+            return reason("empty");
+        }
+        // a link is broken when what it points to cannot be found
+        if self.broken_links
+            && metadata.kind == EntryKind::Symlink
+            && tokio::fs::metadata(path).await.is_err()
+        {
+            return reason("broken link");
+        }
+        if let Some((pattern, _)) = self.names.iter().find(|(_, re)| re.is_match(&name)) {
+            return reason(pattern);
+        }
+        match self.hex_names {
+            Some(min_length) if is_hex_name(&name, min_length) => reason("hex name"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> GarbageRules {
+        GarbageRules {
+            empty: true,
+            broken_links: true,
+            hex_names: Some(16),
+            names: ["*.part", "~$*", "Thumbs.db"]
+                .into_iter()
+                .map(|pattern| (pattern.to_owned(), glob_to_regex(pattern).unwrap()))
+                .collect(),
+        }
+    }
+
+    fn file(size: u64) -> EntryMetadata {
+        EntryMetadata {
+            kind: EntryKind::File,
+            size,
+            ..Default::default()
+        }
+    }
+
+    /// The rule the entry is garbage by
+    async fn rule_of(
+        rules: &GarbageRules,
+        path: &Path,
+        metadata: &EntryMetadata,
+    ) -> Option<String> {
+        match rules.check(path, metadata).await {
+            Some(MatchReason::Garbage(rule)) => Some(rule),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_files_and_names() {
+        let rules = rules();
+        let rule = |name: &str, size| {
+            let path = Path::new("/d").join(name);
+            let rules = &rules;
+            async move { rule_of(rules, &path, &file(size)).await }
+        };
+        assert_eq!(rule("notes.txt", 0).await.as_deref(), Some("empty"));
+        assert_eq!(rule("notes.txt", 10).await, None);
+        assert_eq!(rule("movie.mkv.PART", 10).await.as_deref(), Some("*.part"));
+        assert_eq!(rule("~$report.docx", 10).await.as_deref(), Some("~$*"));
+        assert_eq!(rule("thumbs.db", 10).await.as_deref(), Some("Thumbs.db"));
+        assert_eq!(rule("Thumbs.dbx", 10).await, None);
+        // an empty directory is not an empty file
+        let dir = EntryMetadata {
+            kind: EntryKind::Dir,
+            ..Default::default()
+        };
+        assert_eq!(rule_of(&rules, Path::new("/d/empty"), &dir).await, None);
+    }
+
+    #[test]
+    fn hex_names_need_a_letter_and_enough_digits() {
+        assert!(is_hex_name("3f2504e0-4f89-11d3-9a0c-0305e82c3301.tmp", 16));
+        assert!(is_hex_name("DEADBEEFDEADBEEF", 16));
+        assert!(!is_hex_name("deadbeef", 16));
+        assert!(!is_hex_name("2023010112345678.jpg", 16));
+        assert!(!is_hex_name("deadbeefdeadbeefg", 16));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn broken_links() {
+        let dir = std::env::temp_dir().join(format!("maid-garbage-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (target, link) = (dir.join("target.txt"), dir.join("link"));
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let metadata = EntryMetadata {
+            kind: EntryKind::Symlink,
+            ..Default::default()
+        };
+
+        let rules = rules();
+        assert_eq!(
+            rule_of(&rules, &link, &metadata).await.as_deref(),
+            Some("broken link")
+        );
+        std::fs::write(&target, "a").unwrap();
+        assert_eq!(rule_of(&rules, &link, &metadata).await, None);
+        let off = GarbageRules {
+            broken_links: false,
+            ..rules
+        };
+        std::fs::remove_file(&target).unwrap();
+        assert_eq!(rule_of(&off, &link, &metadata).await, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+use crate::fileop;
+use crate::trash;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const JOURNAL_EXTENSION: &str = "jsonl";
+const UNDONE_EXTENSION: &str = "undone";
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalOperation {
+    Move,
+    Copy,
+    Trash,
+    Remove,
+    /// Replaced by a hard link to the destination
+    Hardlink,
+}
+
+/// One line of the journal, written after an operation succeeds
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub run_id: String,
+    pub operation: JournalOperation,
+    pub source: PathBuf,
+    pub destination: Option<PathBuf>,
+    pub timestamp: String,
+}
+
+/// An append-only record of the file operations done in one run
+pub struct Journal {
+    run_id: String,
+    file: Mutex<Option<fs::File>>,
+}
+
+impl Journal {
+    /// Where the journals are kept, one file per run
+    pub fn journal_dir() -> PathBuf {
+        dirs::data_local_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("maidsweep")
+            .join("journal")
+    }
+
+    pub fn new() -> Self {
+        Journal {
+            run_id: format!(
+                "{}-{}",
+                Local::now().format("%Y%m%dT%H%M%S"),
+                std::process::id()
+            ),
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Whether anything has been written in this run
+    pub fn is_recorded(&self) -> bool {
+        self.file.lock().unwrap().is_some()
+    }
+
+    fn path_of(run_id: &str) -> PathBuf {
+        Self::journal_dir().join(format!("{}.{}", run_id, JOURNAL_EXTENSION))
+    }
+
+    /// Append an entry, the file is only created when the first operation is recorded
+    pub fn record(
+        &self,
+        operation: JournalOperation,
+        source: &Path,
+        destination: Option<&Path>,
+    ) -> io::Result<()> {
+        // undo can be called from anywhere
+        let entry = JournalEntry {
+            run_id: self.run_id.clone(),
+            operation,
+            source: std::path::absolute(source)?,
+            destination: destination.map(std::path::absolute).transpose()?,
+            timestamp: Local::now().to_rfc3339(),
+        };
+        let line = serde_json::to_string(&entry)?;
+
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            fs::create_dir_all(Self::journal_dir())?;
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(Self::path_of(&self.run_id))?,
+            );
+        }
+        let file = file.as_mut().unwrap();
+        writeln!(file, "{}", line)?;
+        file.flush()
+    }
+
+    pub fn load(run_id: &str) -> io::Result<Vec<JournalEntry>> {
+        let reader = BufReader::new(fs::File::open(Self::path_of(run_id))?);
+        reader
+            .lines()
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect()
+    }
+
+    /// The id of the latest run that has not been undone
+    pub fn latest_run() -> Option<String> {
+        let mut runs: Vec<String> = fs::read_dir(Self::journal_dir())
+            .ok()?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != JOURNAL_EXTENSION {
+                    return None;
+                }
+                Some(path.file_stem()?.to_str()?.to_owned())
+            })
+            .collect();
+        // run ids start with the time, so they sort chronologically
+        runs.sort();
+        runs.pop()
+    }
+
+    /// Leave only the entries still to be undone in the journal of a run
+    fn keep(run_id: &str, entries: &[&JournalEntry]) -> io::Result<()> {
+        let mut lines = String::new();
+        for entry in entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        fs::write(Self::path_of(run_id), lines)
+    }
+
+    fn mark_undone(run_id: &str) -> io::Result<()> {
+        let path = Self::path_of(run_id);
+        fs::rename(
+            &path,
+            path.with_extension(format!("{}.{}", JOURNAL_EXTENSION, UNDONE_EXTENSION)),
+        )
+    }
+}
+
+/// Whether anything is at a path, a dangling link included
+fn is_there(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+/// Reverse one entry, or tell why it cannot be reversed
+fn undo_entry(entry: &JournalEntry) -> Result<(), String> {
+    match (entry.operation, &entry.destination) {
+        (JournalOperation::Move, Some(destination)) => {
+            if !is_there(destination) {
+                return Err(format!("{:?} no longer exists", destination));
+            }
+            if is_there(&entry.source) {
+                return Err(format!("{:?} is occupied", entry.source));
+            }
+            if let Some(parent) = entry.source.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fileop::move_path(destination, &entry.source).map_err(|e| e.to_string())
+        }
+        (JournalOperation::Copy, Some(destination)) => {
+            if !is_there(destination) {
+                return Err(format!("{:?} no longer exists", destination));
+            }
+            // the copy might be the only one left
+            if !is_there(&entry.source) {
+                return Err(format!(
+                    "the original {:?} no longer exists, keeping the copy",
+                    entry.source
+                ));
+            }
+            fileop::remove_path(destination).map_err(|e| e.to_string())
+        }
+        (JournalOperation::Trash, Some(destination)) => {
+            if !is_there(destination) {
+                return Err(format!("{:?} is no longer in the trash", destination));
+            }
+            if is_there(&entry.source) {
+                return Err(format!("{:?} is occupied", entry.source));
+            }
+            if let Some(parent) = entry.source.parent() {
+                fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+            }
+            fileop::move_path(destination, &entry.source).map_err(|e| e.to_string())?;
+            trash::forget(destination).map_err(|e| e.to_string())
+        }
+        (JournalOperation::Hardlink, Some(destination)) => {
+            if !fileop::same_file(&entry.source, destination) {
+                return Err(format!(
+                    "{:?} is no longer linked to {:?}",
+                    entry.source, destination
+                ));
+            }
+            fileop::copy_over(destination, &entry.source).map_err(|e| e.to_string())
+        }
+        (JournalOperation::Remove, _) => Err("removed files cannot be restored".to_owned()),
+        (_, None) => Err("no destination is recorded".to_owned()),
+    }
+}
+
+/// Reverse the operations of a run, the latest one if not specified
+pub fn undo(run_id: Option<String>) -> Result<(), Box<dyn Error>> {
+    let run_id = match run_id.or_else(Journal::latest_run) {
+        Some(run_id) => run_id,
+        None => {
+            println!("Nothing to undo");
+            return Ok(());
+        }
+    };
+    let entries = Journal::load(&run_id)?;
+
+    let mut failed = 0;
+    let mut pending = vec![];
+    for entry in entries.iter().rev() {
+        if let Err(reason) = undo_entry(entry) {
+            failed += 1;
+            eprintln!(
+                "Cannot undo {:?} of {:?}: {}",
+                entry.operation, entry.source, reason
+            );
+            // a removal can never be undone, there is no point in trying again
+            if !matches!(entry.operation, JournalOperation::Remove) {
+                pending.push(entry);
+            }
+        }
+    }
+    pending.reverse();
+    if pending.is_empty() {
+        Journal::mark_undone(&run_id)?;
+    } else {
+        Journal::keep(&run_id, &pending)?;
+    }
+
+    println!(
+        "Undid {} of {} operations in run {}",
+        entries.len() - failed,
+        entries.len(),
+        run_id
+    );
+    if !pending.is_empty() {
+        println!(
+            "Kept {} in the journal, call `maid undo {}` to try them again",
+            pending.len(),
+            run_id
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("maid-journal-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// An entry as it is read back from the journal
+    fn entry(operation: JournalOperation, source: &Path, destination: &Path) -> JournalEntry {
+        let line = serde_json::to_string(&JournalEntry {
+            run_id: "run".to_owned(),
+            operation,
+            source: source.to_owned(),
+            destination: Some(destination.to_owned()),
+            timestamp: Local::now().to_rfc3339(),
+        })
+        .unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn moves_are_undone() {
+        let dir = scratch("move");
+        let (source, destination) = (dir.join("a.txt"), dir.join("sorted").join("a.txt"));
+        fs::create_dir(dir.join("sorted")).unwrap();
+        fs::write(&destination, "a").unwrap();
+
+        undo_entry(&entry(JournalOperation::Move, &source, &destination)).unwrap();
+        assert_eq!(fs::read_to_string(&source).unwrap(), "a");
+        assert!(!is_there(&destination));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copies_are_undone_only_with_the_original() {
+        let dir = scratch("copy");
+        let (source, destination) = (dir.join("a.txt"), dir.join("b.txt"));
+        fs::write(&destination, "a").unwrap();
+        let copy = entry(JournalOperation::Copy, &source, &destination);
+
+        assert!(undo_entry(&copy).is_err());
+        assert!(is_there(&destination));
+        fs::write(&source, "a").unwrap();
+        undo_entry(&copy).unwrap();
+        assert!(!is_there(&destination));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dangling_links_count() {
+        let dir = scratch("link");
+        let (source, destination) = (dir.join("link"), dir.join("moved"));
+        std::os::unix::fs::symlink(dir.join("nowhere"), &destination).unwrap();
+
+        // a link that points nowhere is still moved back
+        undo_entry(&entry(JournalOperation::Move, &source, &destination)).unwrap();
+        assert!(is_there(&source) && !is_there(&destination));
+
+        // and it still takes the place of the original
+        std::os::unix::fs::symlink(dir.join("nowhere"), &destination).unwrap();
+        assert!(undo_entry(&entry(JournalOperation::Move, &source, &destination)).is_err());
+        assert!(is_there(&destination));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removals_are_kept_out() {
+        let dir = Path::new("/nowhere");
+        assert!(undo_entry(&entry(JournalOperation::Remove, dir, dir)).is_err());
+    }
+}
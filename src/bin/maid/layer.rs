@@ -0,0 +1,285 @@
+use serde_yaml::{Mapping, Value};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name of the configuration a directory can have of its own
+pub const LOCAL_CONFIG: &str = ".maidsweep.yaml";
+
+/// The configuration of a directory, unless it is the one of the home directory,
+/// which is a layer below already
+pub fn local_config(directory: &Path) -> Option<PathBuf> {
+    let path = directory.join(LOCAL_CONFIG);
+    let home = dirs::home_dir().and_then(|home| fs::canonicalize(home).ok());
+    match fs::canonicalize(directory) {
+        Ok(directory) if Some(&directory) == home.as_ref() => None,
+        _ if path.is_file() => Some(path),
+        _ => None,
+    }
+}
+
+/// The configurations of the directories holding one, from the top down to its parent
+pub fn ancestor_configs(directory: &Path) -> Vec<PathBuf> {
+    let directory = fs::canonicalize(directory).unwrap_or_else(|_| directory.to_owned());
+    let mut configs: Vec<PathBuf> = directory
+        .ancestors()
+        .skip(1)
+        .filter_map(local_config)
+        .collect();
+    configs.reverse();
+    configs
+}
+
+fn key(name: &str) -> Value {
+    Value::String(name.to_owned())
+}
+
+/// The paths of a `include:` or `replace:` entry, one or a list of them
+fn strings(value: Option<Value>) -> Result<Vec<String>, String> {
+    match value {
+        None => Ok(vec![]),
+        Some(value) => match serde_yaml::from_value::<Vec<String>>(value.clone()) {
+            Ok(strings) => Ok(strings),
+            Err(_) => serde_yaml::from_value::<String>(value)
+                .map(|string| vec![string])
+                .map_err(|e| e.to_string()),
+        },
+    }
+}
+
+/// A path in a configuration, from the home directory if it starts with `~/`,
+/// or else from the directory of the file it is in
+fn resolve(path: &str, relative_to: &Path) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None => relative_to.join(path),
+    }
+}
+
+/// Turn the old form of `typical_files`, a tag followed by its patterns, into rules,
+/// so the layers can be merged by the names of the rules
+pub fn normalize(layer: &mut Value) {
+    let typical_files = match layer.get_mut("typical_files") {
+        Some(Value::Mapping(tags)) => std::mem::take(tags),
+        _ => return,
+    };
+    let rules = typical_files
+        .into_iter()
+        .map(|(tag, patterns)| {
+            let mut rule = Mapping::new();
+            rule.insert(key("name"), tag.clone());
+            rule.insert(key("tags"), Value::Sequence(vec![tag]));
+            rule.insert(key("patterns"), patterns);
+            Value::Mapping(rule)
+        })
+        .collect();
+    layer["typical_files"] = Value::Sequence(rules);
+}
+
+/// Read a configuration file, after the ones it includes, in the order they are put over
+/// each other. A file included twice is read once.
+pub fn read_layers(
+    path: &Path,
+    seen: &mut HashSet<PathBuf>,
+) -> Result<Vec<(PathBuf, Value)>, String> {
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    if !seen.insert(canonical) {
+        return Ok(vec![]);
+    }
+    let source =
+        fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let mut layer: Value =
+        serde_yaml::from_str(&source).map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+    if layer.is_null() {
+        layer = Value::Mapping(Mapping::new());
+    }
+    let includes = match layer {
+        Value::Mapping(ref mut mapping) => {
+            strings(mapping.get(&key("replace")).cloned())
+                .map_err(|e| format!("Invalid replace in {}: {}", path.display(), e))?;
+            strings(mapping.remove(&key("include")))
+                .map_err(|e| format!("Invalid include in {}: {}", path.display(), e))?
+        }
+        _ => return Err(format!("Invalid {}: not a mapping", path.display())),
+    };
+    normalize(&mut layer);
+
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut layers = vec![];
+    for include in includes {
+        layers.extend(read_layers(&resolve(&include, directory), seen)?);
+    }
+    layers.push((path.to_owned(), layer));
+    Ok(layers)
+}
+
+/// The name of a rule in a list, like a typical files rule or a series
+fn name_of(value: &Value) -> Option<&Value> {
+    value.get("name")
+}
+
+fn merge_at(base: Value, layer: Value, path: &str, replace: &HashSet<String>) -> Value {
+    match (base, layer) {
+        (Value::Mapping(mut base), Value::Mapping(layer)) => {
+            for (name, value) in layer {
+                let child = match name.as_str() {
+                    Some(name) if path.is_empty() => name.to_owned(),
+                    Some(name) => format!("{}.{}", path, name),
+                    None => path.to_owned(),
+                };
+                let merged = match base.remove(&name) {
+                    Some(below) if !replace.contains(&child) => {
+                        merge_at(below, value, &child, replace)
+                    }
+                    _ => value,
+                };
+                base.insert(name, merged);
+            }
+            Value::Mapping(base)
+        }
+        // rules of the same name are one rule, the others are added
+        (Value::Sequence(mut base), Value::Sequence(layer)) => {
+            for value in layer {
+                let same = name_of(&value)
+                    .and_then(|name| base.iter().position(|below| name_of(below) == Some(name)));
+                match same {
+                    Some(index) => {
+                        let below = std::mem::take(&mut base[index]);
+                        base[index] = merge_at(below, value, path, replace);
+                    }
+                    None if base.contains(&value) => (),
+                    None => base.push(value),
+                }
+            }
+            Value::Sequence(base)
+        }
+        (_, layer) => layer,
+    }
+}
+
+/// Put a layer over the ones below. Lists are added to, except the ones under
+/// the dotted paths of its `replace:`, like `extensions.text`, which are replaced.
+pub fn merge(base: Value, mut layer: Value) -> Result<Value, String> {
+    let replace = match layer {
+        Value::Mapping(ref mut mapping) => strings(mapping.remove(&key("replace")))
+            .map_err(|e| format!("Invalid replace: {}", e))?,
+        _ => vec![],
+    };
+    let replace = replace.into_iter().collect();
+    Ok(merge_at(base, layer, "", &replace))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(source: &str) -> Value {
+        let mut value = serde_yaml::from_str(source).unwrap();
+        normalize(&mut value);
+        value
+    }
+
+    const BASE: &str = "
+extensions:
+  text: [txt, md]
+  image: [jpg]
+garbage:
+  empty: true
+  hex_names: 16
+typical_files:
+  - name: build files
+    tags: [code-project]
+    patterns: ['^Makefile$']
+";
+
+    #[test]
+    fn lists_are_added_to() {
+        let merged = merge(yaml(BASE), yaml("extensions: {text: [md, rst]}")).unwrap();
+        assert_eq!(merged["extensions"]["text"], yaml("[txt, md, rst]"));
+        assert_eq!(merged["extensions"]["image"], yaml("[jpg]"));
+    }
+
+    #[test]
+    fn replaced_lists_are_overridden() {
+        let layer = yaml("replace: extensions.text\nextensions: {text: [rst], image: [png]}");
+        let merged = merge(yaml(BASE), layer).unwrap();
+        assert_eq!(merged["extensions"]["text"], yaml("[rst]"));
+        assert_eq!(merged["extensions"]["image"], yaml("[jpg, png]"));
+        assert!(merged.get("replace").is_none());
+
+        let layer = yaml("replace: [extensions]\nextensions: {text: [rst]}");
+        let merged = merge(yaml(BASE), layer).unwrap();
+        assert_eq!(merged["extensions"], yaml("{text: [rst]}"));
+
+        assert!(merge(yaml(BASE), yaml("replace: {a: b}")).is_err());
+    }
+
+    #[test]
+    fn scalars_are_overridden() {
+        let merged = merge(yaml(BASE), yaml("garbage: {hex_names: 32}")).unwrap();
+        assert_eq!(merged["garbage"], yaml("{empty: true, hex_names: 32}"));
+    }
+
+    #[test]
+    fn rules_of_the_same_name_are_merged() {
+        let layer = yaml(
+            "
+typical_files:
+  - name: build files
+    priority: 5
+    patterns: ['^CMakeLists\\.txt$']
+  - name: album
+    tags: [album]
+    patterns: ['^cover\\.jpg$']
+",
+        );
+        let merged = merge(yaml(BASE), layer).unwrap();
+        assert_eq!(
+            merged["typical_files"],
+            yaml(
+                "
+- name: build files
+  tags: [code-project]
+  patterns: ['^Makefile$', '^CMakeLists\\.txt$']
+  priority: 5
+- name: album
+  tags: [album]
+  patterns: ['^cover\\.jpg$']
+"
+            )
+        );
+    }
+
+    #[test]
+    fn old_typical_files_are_rules() {
+        let layer = yaml("typical_files: {code-project: ['^Cargo\\.toml$']}");
+        assert_eq!(
+            layer["typical_files"],
+            yaml("[{name: code-project, tags: [code-project], patterns: ['^Cargo\\.toml$']}]")
+        );
+    }
+
+    #[test]
+    fn includes_come_first_and_once() {
+        let directory = std::env::temp_dir().join(format!("maid-layers-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let write = |name: &str, source: &str| fs::write(directory.join(name), source).unwrap();
+        write(
+            "a.yaml",
+            "include: [b.yaml, c.yaml]\nextensions: {text: [a]}",
+        );
+        write("b.yaml", "include: c.yaml\nextensions: {text: [b]}");
+        write("c.yaml", "extensions: {text: [c]}");
+
+        let mut seen = HashSet::new();
+        let layers = read_layers(&directory.join("a.yaml"), &mut seen);
+        fs::remove_dir_all(&directory).unwrap();
+
+        let names: Vec<String> = layers
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["c.yaml", "b.yaml", "a.yaml"]);
+    }
+}
@@ -1,21 +1,38 @@
+mod builtin;
+mod check;
 mod config;
 mod context;
 mod datatype;
+mod duplicate;
+mod explain;
+mod fileop;
+mod filter;
+mod garbage;
+mod journal;
+mod layer;
 mod processor;
+mod query;
+mod series;
+mod sniff;
+mod store;
+mod summary;
+mod template;
+mod trash;
+mod typical;
+mod volumes;
 
-use crate::datatype::FileMeta;
+use crate::datatype::{FileMeta, MatchReason};
+use crate::query::Query;
 use clap::Parser;
-use config::MaidConfig;
+use config::{ConfigCommand, MaidCommand, MaidConfig, TrashCommand};
 use context::MaidContext;
-use futures::StreamExt;
-use mongodb::bson::doc;
+use futures::{FutureExt, TryStreamExt};
 use std::{error::Error, io};
 
-use std::path::PathBuf;
 use std::sync::Arc;
 use std::vec;
 
-use crate::processor::{Directory, Exec, Processor};
+use crate::processor::{Choice, Directory, Duplicates, ExecBatch, Processor, Tag};
 
 pub struct MaidSweeper {
     context: Arc<MaidContext>,
@@ -35,100 +52,104 @@ impl MaidSweeper {
     }
 
     fn sweep(&self) -> impl Iterator<Item = tokio::task::JoinHandle<()>> + '_ {
-        // expand synonyms
-        let mut new_tags: Vec<String> = Vec::new();
-        if let Some(tags) = &self.context.get_config().tags {
-            for tag in tags.into_iter() {
-                if let Some(synonyms) = self.context.patterns.synonyms.get(tag) {
-                    new_tags.extend(synonyms.iter().map(|s| s.to_owned()));
-                } else {
-                    new_tags.push(tag.to_string());
-                }
-            }
-        }
-
-        let paths = self
-            .context
-            .config
-            .paths
-            .as_ref()
-            .and_then(|paths| Some(paths.iter().map(|path| path.to_owned()).collect()))
-            .unwrap_or(vec![PathBuf::from(".")]);
+        let paths = self.context.get_config().roots();
         if self.context.is_debug() {
             println!("Tagging {:?}", paths);
         }
 
         // map to paths
-        paths.into_iter().map(move |path|
+        paths.into_iter().map(move |path| {
+            // the configurations of the directories above apply as well
+            self.context
+                .add_local(&path, &layer::ancestor_configs(&path));
             // can fork, as different directories are independent
             tokio::spawn(Self::dispatch(
                 Directory {},
                 self.context.clone(),
                 FileMeta {
                     path: path.to_owned(),
-                    tags: Some(new_tags.clone()),
-                    last_modified: None,
+                    tags: None,
+                    metadata: None,
+                    reasons: vec![],
+                    members: vec![],
                 },
-            )))
+            ))
+        })
     }
-    async fn mongodb_sweep(&self) -> Result<(), Box<dyn Error>> {
-        let mut new_tags: Vec<String> = Vec::new();
-        let database = if let Some(db) = self.context.get_db() {
-            db
+    async fn store_sweep(&self) -> Result<(), Box<dyn Error>> {
+        let store = if let Some(store) = self.context.get_store() {
+            store
         } else {
             return Err(Box::new(io::Error::new(
                 io::ErrorKind::NotFound,
-                "Database not found",
+                "Store not found",
             )));
         };
 
-        if let Some(tags) = &self.context.get_config().tags {
-            for keyword in tags.into_iter() {
-                if let Some(synonyms) = self.context.patterns.synonyms.get(keyword) {
-                    new_tags.extend(synonyms.iter().map(|s| s.to_owned()));
-                } else {
-                    new_tags.push(keyword.to_string());
-                }
-            }
-        }
-
-        let mut cursor = database
-            .collection::<datatype::FileMetaCompat>(processor::COLLECTION_NAME)
-            .find(doc! {"tags": {"$in": new_tags}}, None)
-            .await?;
+        // copies can be anywhere, and the duplicate tag is not saved, so everything is read
+        let query = if self.context.duplicates.is_some() {
+            Query::And(vec![])
+        } else {
+            self.context
+                .query
+                .clone()
+                .resolve_age_tags(&self.context.patterns)
+        };
+        let cursor = store
+            .find_matching(&query)
+            .await
+            .map_err(|e| e as Box<dyn Error>)?;
 
-        let mut tasks: Vec<tokio::task::JoinHandle<()>> = Vec::new();
-        while let Some(item) = cursor.next().await {
-            match item {
-                Ok(item) => {
-                    tasks.push(tokio::spawn(Self::dispatch(
-                        Exec {},
-                        self.context.clone(),
-                        FileMeta {
-                            path: PathBuf::from(&item.path.clone()),
-                            tags: Some(item.tags),
-                            last_modified: Some(item.last_modified),
-                        },
-                    )));
-                }
-                Err(item) => {
-                    println!("Error obtaining data from database: {:?}", item);
-                    return Err(Box::new(item));
-                }
-            }
-        }
-        for task in tasks {
-            if let Err(e) = task.await {
-                eprintln!("Error: {}", e);
-            }
-        }
+        // only keep as many entries in flight as there are jobs
+        cursor
+            .try_for_each_concurrent(self.context.get_config().jobs.get(), |item| {
+                Self::dispatch(
+                    Choice {},
+                    self.context.clone(),
+                    FileMeta {
+                        path: item.path,
+                        tags: Some(item.tags),
+                        metadata: Some(item.metadata),
+                        reasons: vec![MatchReason::Database],
+                        members: item.members,
+                    },
+                )
+                .map(Ok)
+            })
+            .await
+            .map_err(|e| {
+                println!("Error obtaining data from store: {:?}", e);
+                e as Box<dyn Error>
+            })?;
         Ok(())
     }
 }
 
-async fn run(config: MaidConfig) -> Result<(), Box<dyn Error>> {
+async fn run(mut config: MaidConfig) -> Result<(), Box<dyn Error>> {
+    if let Some(command) = config.command.take() {
+        return match command {
+            MaidCommand::Undo { run_id } => journal::undo(run_id),
+            MaidCommand::Explain { path } => {
+                let context = MaidContext::new(config).await?;
+                explain::explain(context, &path)
+                    .await
+                    .map_err(|e| Box::new(e) as Box<dyn Error>)
+            }
+            MaidCommand::Config { command } => match command {
+                ConfigCommand::Check => check::check(&config.config_paths()),
+                ConfigCommand::Init { force } => builtin::init(&config.init_path()?, force),
+                ConfigCommand::Diff => builtin::diff(&config.config_paths()),
+            },
+            MaidCommand::Trash { command } => match command {
+                TrashCommand::List => trash::print_list(),
+                TrashCommand::Restore { items } => trash::restore(&items),
+                TrashCommand::Empty => trash::empty(),
+            },
+        };
+    }
+
     let maid = MaidSweeper {
-        context: Arc::new(MaidContext::new(config).await),
+        context: Arc::new(MaidContext::new(config).await?),
     };
     if !maid.context.get_config().use_mongodb {
         let tasks = maid.sweep();
@@ -138,8 +159,23 @@ async fn run(config: MaidConfig) -> Result<(), Box<dyn Error>> {
                 eprintln!("Error: {}", e);
             }
         }
+        let config = maid.context.get_config();
+        if config.save && !config.dry_run {
+            Tag::prune(maid.context.clone(), &config.roots()).await?;
+        }
     } else {
-        maid.mongodb_sweep().await?;
+        maid.store_sweep().await?;
+    }
+    Duplicates::run(maid.context.clone()).await?;
+    ExecBatch::run(maid.context.clone()).await?;
+
+    maid.context.summary.print();
+    if maid.context.journal.is_recorded() {
+        println!(
+            "Run {} is recorded, call `maid undo {}` to revert it",
+            maid.context.journal.run_id(),
+            maid.context.journal.run_id()
+        );
     }
     Ok(())
 }
@@ -150,8 +186,8 @@ pub async fn main() {
     if config.debug {
         println!("{:?}", config);
     }
-    return match run(config).await {
-        Ok(_) => (),
-        Err(e) => println!("Error: {}", e),
-    };
+    if let Err(e) = run(config).await {
+        println!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
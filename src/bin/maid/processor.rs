@@ -1,18 +1,34 @@
-use crate::config;
+use crate::config::{self, ConflictPolicy, DedupePolicy, MaidConfig};
 use crate::context::MaidContext;
 use crate::datatype;
-use crate::datatype::FileMeta;
+use crate::datatype::{EntryKind, EntryMetadata, FileMeta, MatchReason};
+use crate::duplicate::{self, DUPLICATE_TAG};
+use crate::fileop::{self, FileOpError};
+use crate::garbage::GARBAGE_TAG;
+use crate::journal::JournalOperation;
+use crate::layer;
+use crate::series;
+use crate::sniff::{self, Signature};
+use crate::summary::{Conflict, Resolution};
+use crate::template::{self, Placeholder, Template, Values};
+use crate::trash;
+use crate::typical;
+use crate::volumes;
 use async_trait::async_trait;
+use clap::ValueEnum;
+use futures::stream::{self, StreamExt};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::fmt::{self, Display, Formatter};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::future::Future;
+use std::io;
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
 use tokio::fs::DirEntry;
 use tokio::process::Command;
-
-pub(crate) const COLLECTION_NAME: &str = "tags";
+use tokio::task::JoinHandle;
 
 #[derive(Debug)]
 pub struct ProcessError {
@@ -39,6 +55,14 @@ impl From<&str> for ProcessError {
     }
 }
 
+impl From<FileOpError> for ProcessError {
+    fn from(error: FileOpError) -> Self {
+        ProcessError {
+            message: error.to_string(),
+        }
+    }
+}
+
 impl Error for ProcessError {}
 
 #[async_trait]
@@ -47,7 +71,7 @@ pub trait Processor<R>: Send + Sync {
 }
 
 enum FileResult {
-    Ok(Vec<String>),
+    Ok,
     DirectoryNoTag,
 }
 
@@ -56,6 +80,149 @@ unsafe impl Sync for FileResult {}
 
 pub struct Exec {}
 
+/// Arguments that are passed to the shell as they are
+const SHELL_OPERATORS: [&str; 7] = ["|", "&", "&&", "<", ">", ">>", "<<"];
+
+/// Quote an argument for a POSIX shell
+fn posix_quote(arg: &OsStr) -> OsString {
+    let mut quoted = vec![b'\''];
+    for &byte in arg.as_encoded_bytes() {
+        match byte {
+            b'\'' => quoted.extend_from_slice(br"'\''"),
+            _ => quoted.push(byte),
+        }
+    }
+    quoted.push(b'\'');
+    template::os_string(quoted)
+}
+
+/// Quote an argument for cmd.exe, the way CommandLineToArgvW splits it again:
+/// backslashes are only escaped before a quote
+fn cmd_quote(arg: &OsStr) -> OsString {
+    let mut quoted = vec![b'"'];
+    let mut backslashes = 0;
+    for &byte in arg.as_encoded_bytes() {
+        match byte {
+            b'\\' => backslashes += 1,
+            b'"' => {
+                quoted.resize(quoted.len() + backslashes * 2 + 1, b'\\');
+                quoted.push(b'"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.resize(quoted.len() + backslashes, b'\\');
+                quoted.push(byte);
+                backslashes = 0;
+            }
+        }
+    }
+    // the closing quote must not be escaped
+    quoted.resize(quoted.len() + backslashes * 2, b'\\');
+    quoted.push(b'"');
+    template::os_string(quoted)
+}
+
+impl Exec {
+    /// Substitute the placeholders in the exec arguments
+    fn arguments(
+        context: &MaidContext,
+        path: &Path,
+        file_meta: &FileMeta,
+    ) -> Result<Vec<OsString>, ProcessError> {
+        let exec_args = if let Some(ref args) = context.get_config().exec_args {
+            args
+        } else {
+            return Err("No exec arguments provided".into());
+        };
+
+        let tags = file_meta.tags.as_deref().unwrap_or_default();
+        let values = Values {
+            path,
+            tags,
+            roots: context.get_config().paths.as_deref().unwrap_or_default(),
+            modified: file_meta.metadata.as_ref().map(|m| m.last_modified),
+            file: path,
+        };
+        let arguments: Vec<OsString> = exec_args
+            .iter()
+            .map(|arg| Template::parse(arg).expand(&values))
+            .collect();
+
+        if context.is_debug() {
+            println!("tags: {:?}", tags);
+            println!("exec_args: {:?}", exec_args);
+            println!("arguments: {:?}", arguments);
+        }
+        Ok(arguments)
+    }
+
+    /// Join the arguments into a command line for the shell, quoting all but the operators
+    fn shell_line(arguments: &[OsString], quote: fn(&OsStr) -> OsString) -> OsString {
+        let mut line = OsString::new();
+        for (index, arg) in arguments.iter().enumerate() {
+            if index > 0 {
+                line.push(" ");
+            }
+            match arg.to_str() {
+                Some(operator) if SHELL_OPERATORS.contains(&operator) => line.push(operator),
+                _ => line.push(quote(arg)),
+            }
+        }
+        line
+    }
+
+    /// The command line as it would be typed in a shell, for printing
+    fn display(context: &MaidContext, arguments: &[OsString]) -> String {
+        if context.get_config().exec_shell {
+            return Self::shell_line(arguments, posix_quote)
+                .to_string_lossy()
+                .into_owned();
+        }
+        arguments
+            .iter()
+            .map(|arg| posix_quote(arg).to_string_lossy().into_owned())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    fn command_line(
+        context: &MaidContext,
+        path: &Path,
+        file_meta: &FileMeta,
+    ) -> Result<String, ProcessError> {
+        let arguments = Self::arguments(context, path, file_meta)?;
+        Ok(Self::display(context, &arguments))
+    }
+
+    /// Run the program directly, or through the shell with --exec-shell
+    fn command(context: &MaidContext, arguments: &[OsString]) -> Result<Command, ProcessError> {
+        if !context.get_config().exec_shell {
+            let (program, args) = match arguments.split_first() {
+                Some(split) => split,
+                None => return Err("No exec arguments provided".into()),
+            };
+            let mut command = Command::new(program);
+            command.args(args);
+            return Ok(command);
+        }
+
+        let (shell, arg1) = match config::find_shell() {
+            Some(shell) => shell,
+            None => return Err("No shell found!".into()),
+        };
+        // cmd.exe takes /c, and does not understand single quotes
+        let quote = if arg1 == "/c" { cmd_quote } else { posix_quote };
+        let exec_str = Self::shell_line(arguments, quote);
+        if context.is_debug() {
+            println!("exec_str: {} {} {:?}", shell, arg1, exec_str);
+        }
+
+        let mut command = Command::new(shell);
+        command.arg(arg1).arg(exec_str);
+        Ok(command)
+    }
+}
+
 #[async_trait]
 impl Processor<()> for Exec {
     async fn process(
@@ -63,121 +230,184 @@ impl Processor<()> for Exec {
         context: Arc<MaidContext>,
         file_meta: FileMeta,
     ) -> Result<(), ProcessError> {
-        let path = file_meta.path;
-        let tags = file_meta.tags.unwrap_or_default();
+        // the command is run on each file of a group
+        for path in file_meta.files() {
+            let arguments = Self::arguments(&context, &path, &file_meta)?;
+
+            let _permit = context.jobs.acquire().await;
+            let status = Self::command(&context, &arguments)?
+                .spawn()
+                .map_err(|e| ProcessError::from(format!("Failed to execute command: {}", e)))?
+                .wait()
+                .await
+                .map_err(|e| ProcessError::from(format!("Failed to execute command: {}", e)))?;
+            if !status.success() {
+                return Err(format!("Command failed on {:?}: {}", path, status).into());
+            }
+        }
+        Ok(())
+    }
+}
 
-        let exec_args = if let Some(ref args) = context.get_config().exec_args {
-            args
-        } else {
-            return Err("No exec arguments provided".into());
-        };
+/// Collects the entries for --exec-batch, and runs the commands when the sweep is done
+pub struct ExecBatch;
 
-        // To properly and safely replace the arguments, a LR(0) parser is needed.
-        // But for now, we just opt for a simple string replace.
+/// How long a command line can be, kept well below the limits of the systems
+#[cfg(windows)]
+const ARG_MAX: usize = 32 * 1024 - 1;
+#[cfg(not(windows))]
+const ARG_MAX: usize = 128 * 1024;
 
-        let path_str = String::from(path.to_str().unwrap_or(""));
-        let basename: String = path
-            .file_name()
-            .and_then(OsStr::to_str)
-            .unwrap_or("")
-            .into();
-        let dirname: String = path
-            .parent()
-            .and_then(Path::to_str)
-            .unwrap_or_default()
-            .into();
-        let basename_no_ext: String = path
-            .file_stem()
-            .and_then(OsStr::to_str)
-            .unwrap_or_default()
-            .into();
-
-        let path_with_no_ext: String = path.with_extension("").to_str().unwrap_or_default().into();
-        let tags_str = format!("#{}", tags.join("#"));
+/// The space an argument takes on the command line, with its terminator and pointer
+fn arg_size(arg: &OsStr) -> usize {
+    arg.len() + 1 + std::mem::size_of::<usize>()
+}
 
-        if context.is_debug() {
-            println!("tags: {:?}", tags);
-            println!("path_str: {}", path_str);
-            println!(
-                "exec_args: {}",
-                exec_args
-                    .iter()
-                    .map(|osstr| osstr.clone().into_string().unwrap_or_default())
-                    .collect::<Vec<String>>()
-                    .join(" ")
-            );
+impl ExecBatch {
+    pub fn add(context: &MaidContext, file_meta: FileMeta) {
+        // group by the first tag, so that {1} means the same for the whole batch
+        let tag = file_meta
+            .tags
+            .as_ref()
+            .and_then(|tags| tags.first())
+            .cloned()
+            .unwrap_or_default();
+        let mut exec_batch = context.exec_batch.lock().unwrap();
+        let batch = exec_batch.entry(tag).or_default();
+        // the files of a group are arguments of their own
+        for path in file_meta.files() {
+            batch.push(FileMeta {
+                path,
+                members: vec![],
+                ..file_meta.clone()
+            });
         }
+    }
 
-        let replaced_args = exec_args.iter().map(|arg| {
-            // do not replace shell special strings
-            match arg.to_str().unwrap_or("") {
-                "|" | "&" | "&&" | "<" | ">" | ">>" | "<<" => {
-                    return arg.clone().to_str().unwrap_or("").to_owned();
+    /// Split the entries into commands that fit on a command line,
+    /// returns how many entries each command has and its arguments
+    fn commands(
+        context: &MaidContext,
+        templates: &[Template],
+        entries: &[FileMeta],
+    ) -> Vec<(usize, Vec<OsString>)> {
+        let roots = context.get_config().paths.as_deref().unwrap_or_default();
+        // {1} is the same for the whole batch, other placeholders make the argument repeat
+        let repeated: Vec<bool> = templates
+            .iter()
+            .map(|template| {
+                template
+                    .placeholders()
+                    .any(|placeholder| *placeholder != Placeholder::Tag(1))
+            })
+            .collect();
+        // every template expanded for every entry
+        let expanded: Vec<Vec<OsString>> = entries
+            .iter()
+            .map(|file_meta| {
+                let values = Values {
+                    path: &file_meta.path,
+                    tags: file_meta.tags.as_deref().unwrap_or_default(),
+                    roots,
+                    modified: file_meta.metadata.as_ref().map(|m| m.last_modified),
+                    file: file_meta.members.first().unwrap_or(&file_meta.path),
+                };
+                templates
+                    .iter()
+                    .map(|template| template.expand(&values))
+                    .collect()
+            })
+            .collect();
+
+        let assemble = |chunk: &[Vec<OsString>]| -> (usize, Vec<OsString>) {
+            let mut arguments = vec![];
+            for (index, repeated) in repeated.iter().enumerate() {
+                if *repeated {
+                    arguments.extend(chunk.iter().map(|args| args[index].clone()));
+                } else {
+                    arguments.push(chunk[0][index].clone());
                 }
-                _ => (),
-            }
-
-            let mut replaced_arg = arg.clone().into_string().unwrap();
-            if tags.len() > 0 {
-                replaced_arg = replaced_arg.replace("{1}", &tags[0]);
-            }
-            if tags.len() > 1 {
-                replaced_arg = replaced_arg.replace("{2}", &tags[1]);
-            }
-            if tags.len() > 2 {
-                replaced_arg = replaced_arg.replace("{3}", &tags[2]);
-            }
-            replaced_arg = replaced_arg
-                .replace("{0}", &tags_str)
-                .replace("{.}", &path_with_no_ext)
-                .replace("{/.}", &basename_no_ext)
-                .replace("{//}", &dirname)
-                .replace("{/}", &basename)
-                .replace("{}", &path_str)
-                // shell escape and quote
-                .replace(r"\", r"\\")
-                .replace(r#"""#, r#"\""#);
-            // surround with quotes
-            format!(r#""{}""#, replaced_arg)
-        });
+            }
+            (chunk.len(), arguments)
+        };
 
-        // TODO: pass multithreading context
-        let find_shell = config::find_shell();
-        let mut shell_comm = match find_shell {
-            Some((ref shell, ref _arg1)) => tokio::process::Command::new(shell),
-            _ => return Result::Err("No shell found!".into()),
+        let size_of = |args: &Vec<OsString>, of_repeated: bool| -> usize {
+            repeated
+                .iter()
+                .zip(args.iter())
+                .filter(|(is_repeated, _)| **is_repeated == of_repeated)
+                .map(|(_, arg)| arg_size(arg))
+                .sum()
         };
+        let fixed_size = expanded.first().map_or(0, |args| size_of(args, false));
+
+        let mut commands = vec![];
+        let mut start = 0;
+        let mut size = fixed_size;
+        for (index, args) in expanded.iter().enumerate() {
+            let item_size = size_of(args, true);
+            // an entry too long for a command line of its own still gets one
+            if index > start && size + item_size > ARG_MAX {
+                commands.push(assemble(&expanded[start..index]));
+                start = index;
+                size = fixed_size;
+            }
+            size += item_size;
+        }
+        if start < expanded.len() {
+            commands.push(assemble(&expanded[start..]));
+        }
+        commands
+    }
 
-        let exec_str = replaced_args.into_iter().collect::<Vec<String>>().join(" ");
+    /// Run the commands on everything collected
+    pub async fn run(context: Arc<MaidContext>) -> Result<(), ProcessError> {
+        let templates: Vec<Template> = match context.get_config().exec_batch_args {
+            Some(ref args) => args.iter().map(Template::parse).collect(),
+            None => return Ok(()),
+        };
 
-        if context.is_debug() {
-            println!("exec_str: {:?} {}", find_shell.as_ref().unwrap(), exec_str);
-        }
+        let groups = std::mem::take(&mut *context.exec_batch.lock().unwrap());
+        for (tag, entries) in groups {
+            for (count, arguments) in Self::commands(&context, &templates, &entries) {
+                if context.get_config().dry_run {
+                    println!(
+                        "[dry-run] exec-batch ({} entries tagged {}) -> {}",
+                        count,
+                        tag,
+                        Exec::display(&context, &arguments)
+                    );
+                    continue;
+                }
 
-        match shell_comm
-            .arg(&find_shell.as_ref().unwrap().1)
-            .arg(exec_str)
-            .spawn()
-            .expect("Failed to execute command")
-            .wait()
-            .await
-        {
-            Ok(status) => {
+                let _permit = context.jobs.acquire().await;
+                let status = Exec::command(&context, &arguments)?
+                    .spawn()
+                    .map_err(|e| ProcessError::from(format!("Failed to execute command: {}", e)))?
+                    .wait()
+                    .await
+                    .map_err(|e| ProcessError::from(format!("Failed to execute command: {}", e)))?;
                 if !status.success() {
-                    return Result::Err("Command failed".into());
+                    eprintln!(
+                        "Error: Command failed on a batch of {} entries tagged {}: {}",
+                        count, tag, status
+                    );
                 }
             }
-            Err(e) => {
-                return Result::Err(ProcessError::from(format!(
-                    "Failed to execute command: {}",
-                    e
-                )));
-            }
         }
         Ok(())
     }
 }
 
+/// Read the metadata on a blocking thread, as directories are summed up recursively
+pub async fn read_metadata(path: &Path) -> Result<EntryMetadata, ProcessError> {
+    let path = path.to_owned();
+    tokio::task::spawn_blocking(move || fileop::entry_metadata(&path))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(ProcessError::from)
+}
+
 pub struct Tag;
 
 #[async_trait]
@@ -187,61 +417,247 @@ impl Processor<()> for Tag {
         context: Arc<MaidContext>,
         file_meta: FileMeta,
     ) -> Result<(), ProcessError> {
-        let collection = context
-            .get_db()
-            .unwrap()
-            .collection::<datatype::FileMetaCompat>(COLLECTION_NAME);
-        // TODO: remove copy
-        if let Some(tags) = file_meta.tags {
-            match collection
-                .insert_one(
-                    datatype::FileMetaCompat {
-                        path: file_meta.path,
-                        tags: tags.to_vec(),
-                        last_modified: 0,
-                    },
-                    None,
-                )
-                .await
+        let store = context.get_store().ok_or("No store to save to")?;
+        let mut tags = file_meta.tags.ok_or("No tags provided")?;
+        // they would be out of date soon
+        let patterns = context.patterns_of(&file_meta.path);
+        tags.retain(|tag| !patterns.is_age_tag(tag) && tag != DUPLICATE_TAG);
+        let path = fileop::canonical_path(&file_meta.path)?;
+        let members = file_meta
+            .members
+            .iter()
+            .map(|member| fileop::canonical_path(member))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut metadata = match file_meta.metadata {
+            Some(metadata) => metadata,
+            None => read_metadata(&path).await?,
+        };
+
+        let indexed = store
+            .find(&path)
+            .await
+            .map_err(|e| format!("Failed to look up {:?}: {}", path, e))?;
+        if let Some(indexed) = indexed {
+            let unchanged = indexed.metadata.last_modified == metadata.last_modified
+                && indexed.metadata.size == metadata.size;
+            // the hash holds as long as the content is the same
+            if unchanged && metadata.hash.is_none() {
+                metadata.hash = indexed.metadata.hash.clone();
+            }
+            // it has not changed since it was saved
+            if unchanged
+                && indexed.tags == tags
+                && indexed.metadata.hash == metadata.hash
+                && indexed.members == members
             {
-                Ok(_) => Ok(()),
-                Err(e) => {
-                    return Result::Err(ProcessError::from(format!(
-                        "Failed to insert file meta: {}",
-                        e
-                    )));
+                return Ok(());
+            }
+        }
+
+        // the files of a group are saved as one entry
+        for member in members.iter() {
+            store
+                .remove(member)
+                .await
+                .map_err(|e| format!("Failed to remove entry {:?}: {}", member, e))?;
+        }
+        store
+            .save(datatype::FileMetaCompat {
+                path,
+                tags,
+                metadata,
+                members,
+            })
+            .await
+            .map_err(|e| ProcessError::from(format!("Failed to save file meta: {}", e)))
+    }
+}
+
+impl Tag {
+    /// Remove the saved entries under the roots that are no longer on disk
+    pub async fn prune(context: Arc<MaidContext>, roots: &[PathBuf]) -> Result<(), ProcessError> {
+        let store = context.get_store().ok_or("No store to prune")?;
+        let mut removed = 0;
+        for root in roots {
+            let root = fileop::canonical_path(root)?;
+            let paths = store
+                .paths_under(&root)
+                .await
+                .map_err(|e| format!("Failed to list entries under {:?}: {}", root, e))?;
+            for path in paths {
+                if fs::symlink_metadata(&path).is_ok() {
+                    continue;
+                }
+                // a group entry is not on disk, but its files are
+                let entry = store
+                    .find(&path)
+                    .await
+                    .map_err(|e| format!("Failed to look up {:?}: {}", path, e))?;
+                if entry.is_some_and(|entry| {
+                    entry
+                        .members
+                        .iter()
+                        .any(|member| fs::symlink_metadata(member).is_ok())
+                }) {
+                    continue;
                 }
+                store
+                    .remove(&path)
+                    .await
+                    .map_err(|e| format!("Failed to remove entry {:?}: {}", path, e))?;
+                removed += 1;
             }
-        } else {
-            Result::Err("No tags provided".into())
         }
+        if removed > 0 {
+            println!(
+                "Removed {} entries no longer on disk from the store",
+                removed
+            );
+        }
+        Ok(())
     }
 }
 
 enum Operation {
     Copy(PathBuf),
     Move(PathBuf),
+    Trash,
     Remove,
+    /// Replace with a hard link to the given file, which has the same content
+    Link(PathBuf),
 }
 
 struct Move {
     op: Operation,
 }
 
+/// What became of an entry after the operation
+enum Outcome {
+    Placed(PathBuf),
+    Removed,
+    Skipped,
+}
+
 impl Move {
     pub fn new(op: Operation) -> Self {
         Self { op }
     }
+
+    /// Put the entry at the first numbered name nobody else has taken
+    fn place_numbered(
+        source: &Path,
+        target: &Path,
+        operation: fn(&Path, &Path) -> Result<(), FileOpError>,
+    ) -> Result<PathBuf, FileOpError> {
+        let mut number = 1;
+        loop {
+            let numbered = fileop::numbered(target, number);
+            match operation(source, &numbered) {
+                Err(e) if e.source.kind() == io::ErrorKind::AlreadyExists => number += 1,
+                result => return result.map(|_| numbered),
+            }
+        }
+    }
+
+    /// Copy or move the entry to the target, taking care of conflicts.
+    /// The operation claims the target itself, so entries placed at the same time
+    /// never end up at the same path.
+    fn place(
+        context: &MaidContext,
+        source: &Path,
+        target: PathBuf,
+        operation: fn(&Path, &Path) -> Result<(), FileOpError>,
+    ) -> Result<Outcome, FileOpError> {
+        match operation(source, &target) {
+            Err(e) if e.source.kind() == io::ErrorKind::AlreadyExists => (),
+            result => return result.map(|_| Outcome::Placed(target)),
+        }
+        let (placed, resolution) = match context.get_config().on_conflict {
+            ConflictPolicy::Skip => (None, Resolution::Skipped),
+            // the one overwritten goes to the trash first, so undo can bring it back
+            ConflictPolicy::Overwrite => {
+                let trashed = trash::trash(&target)?;
+                context
+                    .journal
+                    .record(JournalOperation::Trash, &target, Some(&trashed))
+                    .map_err(|source| FileOpError {
+                        operation: "journal the trashing of",
+                        path: target.clone(),
+                        source,
+                    })?;
+                operation(source, &target)?;
+                (Some(target.clone()), Resolution::Overwritten)
+            }
+            ConflictPolicy::HashCompare
+                if fileop::hash_path(source)? == fileop::hash_path(&target)? =>
+            {
+                (None, Resolution::Identical)
+            }
+            ConflictPolicy::Rename | ConflictPolicy::HashCompare => {
+                let renamed = Self::place_numbered(source, &target, operation)?;
+                (Some(renamed.clone()), Resolution::Renamed(renamed))
+            }
+        };
+        context.summary.add_conflict(Conflict {
+            source: source.to_owned(),
+            destination: target,
+            resolution,
+        });
+        Ok(placed.map_or(Outcome::Skipped, Outcome::Placed))
+    }
+
+    /// The folder a series is kept together in, named after what its files have in common
+    fn series_folder(context: &MaidContext, file_meta: &FileMeta) -> Option<OsString> {
+        if file_meta.metadata.as_ref().map(|m| m.kind) != Some(EntryKind::Series) {
+            return None;
+        }
+        let first = &file_meta.path;
+        series::folder_of(&context.patterns_of(first).series, first)
+            .map(OsString::from)
+            .or_else(|| first.file_stem().map(OsStr::to_owned))
+    }
+
+    /// The subdirectory of the destination the entry goes into, laid out by --layout
+    fn subdir(context: &MaidContext, file_meta: &FileMeta) -> Result<PathBuf, ProcessError> {
+        let tags = file_meta.tags.as_deref().unwrap_or_default();
+        let layout = match context.get_config().layout {
+            Some(ref layout) => layout,
+            None => {
+                return match tags.first() {
+                    Some(tag) => Ok(PathBuf::from(tag)),
+                    None => Err(format!(
+                        "No tags available for {}",
+                        file_meta.path.to_str().unwrap_or_default()
+                    )
+                    .into()),
+                }
+            }
+        };
+
+        let subdir = layout.expand(&Values {
+            path: &file_meta.path,
+            tags,
+            roots: context.get_config().paths.as_deref().unwrap_or_default(),
+            modified: file_meta.metadata.as_ref().map(|m| m.last_modified),
+            file: file_meta.members.first().unwrap_or(&file_meta.path),
+        });
+
+        // keep it inside the destination
+        Ok(Path::new(&subdir)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect())
+    }
 }
 
 fn wrap_error<E>(e: E) -> ProcessError
 where
     E: Error,
 {
-    ProcessError::from(format!("IO Error: {}", e.to_string()))
+    ProcessError::from(format!("IO Error: {}", e))
 }
 
-fn create_path(path: PathBuf, subdir: &str) -> Result<PathBuf, ProcessError> {
+fn create_path(path: PathBuf, subdir: &Path) -> Result<PathBuf, ProcessError> {
     let target_path = path.join(subdir);
     let result = if !target_path.exists() {
         fs::create_dir_all(&target_path)
@@ -250,123 +666,325 @@ fn create_path(path: PathBuf, subdir: &str) -> Result<PathBuf, ProcessError> {
     };
 
     if result.is_err() {
-        return Result::Err(format!("Failed to create directory: {}", subdir).into());
+        return Result::Err(format!("Failed to create directory: {}", subdir.display()).into());
     }
     Ok(target_path)
 }
 
+impl Move {
+    /// Do the operation on one file, copies and moves go into `destination`
+    async fn apply(
+        &self,
+        context: &Arc<MaidContext>,
+        source: PathBuf,
+        destination: Option<&Path>,
+    ) -> Result<(), ProcessError> {
+        let target =
+            destination.map(|destination| destination.join(source.file_name().unwrap_or_default()));
+        let (operation, result) = match self.op {
+            Operation::Copy(_) => {
+                let context = context.clone();
+                let source = source.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    Self::place(&context, &source, target.unwrap(), fileop::copy_path)
+                });
+                (JournalOperation::Copy, result.await)
+            }
+            Operation::Move(_) => {
+                let context = context.clone();
+                let source = source.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    Self::place(&context, &source, target.unwrap(), fileop::move_path)
+                });
+                (JournalOperation::Move, result.await)
+            }
+            Operation::Trash => {
+                let source = source.clone();
+                let result =
+                    tokio::task::spawn_blocking(move || trash::trash(&source).map(Outcome::Placed));
+                (JournalOperation::Trash, result.await)
+            }
+            Operation::Remove => {
+                let source = source.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    fileop::remove_path(&source).map(|_| Outcome::Removed)
+                });
+                (JournalOperation::Remove, result.await)
+            }
+            Operation::Link(ref original) => {
+                let original = original.clone();
+                let source = source.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    fileop::hard_link_over(&original, &source).map(|_| Outcome::Placed(original))
+                });
+                (JournalOperation::Hardlink, result.await)
+            }
+        };
+
+        // write down what has been done, so it can be undone
+        let destination = match result.map_err(wrap_error)?? {
+            Outcome::Placed(destination) => Some(destination),
+            Outcome::Removed => None,
+            Outcome::Skipped => return Ok(()),
+        };
+        context
+            .journal
+            .record(operation, &source, destination.as_deref())
+            .map_err(wrap_error)
+    }
+}
+
 #[async_trait]
 impl Processor<()> for Move {
     async fn process(
         self,
-        _context: Arc<MaidContext>,
+        context: Arc<MaidContext>,
         file_meta: FileMeta,
     ) -> Result<(), ProcessError> {
         // move the file to the directory
         // if the directory does not exist, create it
+        let _permit = context.jobs.acquire().await;
+        let destination = match self.op {
+            Operation::Copy(ref path) | Operation::Move(ref path) => {
+                let mut subdir = Self::subdir(&context, &file_meta)?;
+                // a series is kept together in a folder of its own
+                if let Some(folder) = Self::series_folder(&context, &file_meta) {
+                    subdir.push(folder);
+                }
+                Some(create_path(path.clone(), &subdir)?)
+            }
+            _ => None,
+        };
+        for source in file_meta.files() {
+            self.apply(&context, source, destination.as_deref()).await?;
+        }
+        Ok(())
+    }
+}
 
-        let tags = file_meta.tags.as_ref();
-        let subdir = if let Some(t) = tags {
-            if t.len() > 0 {
-                &t[0]
-            } else {
-                return Result::Err(
-                    format!(
-                        "No tags available for {}",
-                        file_meta.path.to_str().unwrap_or_default()
-                    )
-                    .into(),
-                );
+/// What to do with the entries, decided by the command line arguments
+#[derive(Clone, Debug)]
+enum Action {
+    Copy(PathBuf),
+    Save,
+    Move(PathBuf),
+    Exec,
+    ExecBatch,
+    Trash,
+    Remove,
+    Dedupe(DedupePolicy),
+}
+
+impl Action {
+    fn from_config(config: &MaidConfig) -> Option<Self> {
+        if let Some(ref path) = config.copy_to {
+            Some(Action::Copy(path.clone()))
+        } else if config.save {
+            Some(Action::Save)
+        } else if let Some(ref path) = config.move_to {
+            Some(Action::Move(path.clone()))
+        } else if config.exec_args.is_some() {
+            Some(Action::Exec)
+        } else if config.exec_batch_args.is_some() {
+            Some(Action::ExecBatch)
+        } else if config.trash {
+            Some(Action::Trash)
+        } else if config.delete {
+            Some(Action::Remove)
+        } else {
+            config.dedupe.map(Action::Dedupe)
+        }
+    }
+}
+
+/// Prints what would be done to an entry, used by dry runs
+struct Plan {
+    action: Action,
+}
+
+#[async_trait]
+impl Processor<()> for Plan {
+    async fn process(
+        self,
+        context: Arc<MaidContext>,
+        file_meta: FileMeta,
+    ) -> Result<(), ProcessError> {
+        let tags = file_meta.tags.clone().unwrap_or_default();
+        let file_name = Move::series_folder(&context, &file_meta)
+            .unwrap_or_else(|| file_meta.path.file_name().unwrap_or_default().to_owned());
+        let placed = |path: &PathBuf| -> Result<String, ProcessError> {
+            let subdir = path.join(Move::subdir(&context, &file_meta)?);
+            // the volumes of an archive go into the directory side by side, under their own names
+            let (target, taken) =
+                if file_meta.metadata.as_ref().map(|m| m.kind) == Some(EntryKind::Volumes) {
+                    let taken = file_meta
+                        .files()
+                        .iter()
+                        .map(|member| subdir.join(member.file_name().unwrap_or_default()))
+                        .collect();
+                    (subdir, taken)
+                } else {
+                    let target = subdir.join(&file_name);
+                    (target.clone(), vec![target])
+                };
+            if taken.iter().all(|path| fs::symlink_metadata(path).is_err()) {
+                return Ok(format!("{:?}", target));
             }
+            let policy = context
+                .get_config()
+                .on_conflict
+                .to_possible_value()
+                .unwrap();
+            Ok(format!("{:?} (taken, {})", target, policy.get_name()))
+        };
+        let (action, destination) = match self.action {
+            Action::Copy(ref path) => ("copy", Some(placed(path)?)),
+            Action::Move(ref path) => ("move", Some(placed(path)?)),
+            Action::Save => ("save", Some(context.get_config().store_url().to_owned())),
+            Action::Exec => (
+                "exec",
+                Some(
+                    file_meta
+                        .files()
+                        .iter()
+                        .map(|path| Exec::command_line(&context, path, &file_meta))
+                        .collect::<Result<Vec<_>, _>>()?
+                        .join("; "),
+                ),
+            ),
+            Action::ExecBatch => ("exec-batch", None),
+            Action::Trash => ("trash", Some(format!("{:?}", trash::trash_dir()))),
+            Action::Remove => ("remove", None),
+            Action::Dedupe(DedupePolicy::Hardlink) => (
+                "hardlink",
+                Duplicates::original_of(&file_meta).map(|original| format!("{:?}", original)),
+            ),
+            Action::Dedupe(_) => ("trash", Some(format!("{:?}", trash::trash_dir()))),
+        };
+
+        let reasons = file_meta
+            .reasons
+            .iter()
+            .map(|reason| reason.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let source = if file_meta.members.is_empty() {
+            format!("{:?}", file_meta.path)
         } else {
-            return Result::Err(
-                format!(
-                    "No tags available for {}",
-                    file_meta.path.to_str().unwrap_or_default()
-                )
-                .into(),
-            );
+            format!("{:?} ({} files)", file_meta.path, file_meta.members.len())
         };
+        match destination {
+            Some(destination) => println!(
+                "[dry-run] {} {} -> {} (tags: {}; matched: {})",
+                action,
+                source,
+                destination,
+                tags.join(", "),
+                reasons
+            ),
+            None => println!(
+                "[dry-run] {} {} (tags: {}; matched: {})",
+                action,
+                source,
+                tags.join(", "),
+                reasons
+            ),
+        }
+        Ok(())
+    }
+}
 
-        let exit_result = match (std::env::consts::OS, self.op) {
-            ("windows", Operation::Copy(path)) => {
-                Command::new("move")
-                    .arg(file_meta.path)
-                    .arg(create_path(path, subdir)?)
-                    .spawn()
-                    .map_err(wrap_error)?
-                    .wait()
-                    .await
+pub struct Choice;
+
+impl Choice {
+    /// Tag the entry by its age, and do what was asked if it is one of those to sweep
+    async fn decide(
+        context: Arc<MaidContext>,
+        mut file_meta: FileMeta,
+    ) -> Result<(), ProcessError> {
+        // age tags go after the others, so the first tag is still what the entry is
+        if let Some(ref metadata) = file_meta.metadata {
+            let patterns = context.patterns_of(&file_meta.path);
+            let tags = file_meta.tags.get_or_insert_with(Vec::new);
+            for tag in patterns.age_tags_of(metadata) {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                    file_meta.reasons.push(MatchReason::Age(tag.clone()));
+                }
             }
-            ("windows", Operation::Move(path)) => {
-                Command::new("xcopy")
-                    .arg(file_meta.path)
-                    .arg(create_path(path, subdir)?)
-                    .spawn()
-                    .map_err(wrap_error)?
-                    .wait()
-                    .await
+        }
+        // an entry explained is only tagged
+        if let Some(ref trace) = context.trace {
+            trace.tagged(file_meta);
+            return Ok(());
+        }
+        if !context.query.matches(
+            file_meta.tags.as_deref().unwrap_or_default(),
+            file_meta.metadata.as_ref(),
+        ) {
+            return Ok(());
+        }
+
+        let action = match Action::from_config(context.get_config()) {
+            Some(action) => action,
+            None => {
+                println!("No tasks specified");
+                return Ok(());
             }
-            ("windows", Operation::Remove) => {
-                Command::new("del")
-                    .arg("/f")
-                    .arg("/q")
-                    .arg(file_meta.path)
-                    .spawn()
-                    .map_err(wrap_error)?
-                    .wait()
-                    .await
+        };
+
+        // batches are planned or run when everything is collected
+        if let Action::ExecBatch = action {
+            ExecBatch::add(&context, file_meta);
+            return Ok(());
+        }
+        // only the copies are deduplicated, not the one kept
+        if let Action::Dedupe(_) = action {
+            if Duplicates::original_of(&file_meta).is_none() {
+                return Ok(());
             }
-            (_, Operation::Move(path)) => {
-                Command::new("mv")
-                    .arg(file_meta.path)
-                    .arg(create_path(path, subdir)?)
-                    .spawn()
-                    .map_err(wrap_error)?
-                    .wait()
-                    .await
+        }
+
+        if context.get_config().dry_run {
+            return Plan { action }.process(context, file_meta).await;
+        }
+
+        // parallelize?
+        let task = match action {
+            Action::Copy(path) => {
+                tokio::task::spawn(Move::new(Operation::Copy(path)).process(context, file_meta))
             }
-            (_, Operation::Copy(path)) => {
-                Command::new("cp")
-                    .arg("-r")
-                    .arg(file_meta.path)
-                    .arg(create_path(path, subdir)?)
-                    .spawn()
-                    .map_err(wrap_error)?
-                    .wait()
-                    .await
+            Action::Save => tokio::task::spawn(Tag {}.process(context, file_meta)),
+            Action::Move(path) => {
+                tokio::task::spawn(Move::new(Operation::Move(path)).process(context, file_meta))
             }
-            (_, Operation::Remove) => {
-                Command::new("rm")
-                    .arg("-rf")
-                    .arg(file_meta.path)
-                    .spawn()
-                    .map_err(wrap_error)?
-                    .wait()
-                    .await
+            Action::Exec => tokio::task::spawn(Exec {}.process(context, file_meta)),
+            Action::ExecBatch => unreachable!(),
+            Action::Trash => {
+                tokio::task::spawn(Move::new(Operation::Trash).process(context, file_meta))
+            }
+            Action::Remove => {
+                tokio::task::spawn(Move::new(Operation::Remove).process(context, file_meta))
+            }
+            Action::Dedupe(DedupePolicy::Hardlink) => {
+                let original = Duplicates::original_of(&file_meta).unwrap().to_owned();
+                tokio::task::spawn(Move::new(Operation::Link(original)).process(context, file_meta))
+            }
+            Action::Dedupe(_) => {
+                tokio::task::spawn(Move::new(Operation::Trash).process(context, file_meta))
             }
         };
 
-        match exit_result {
-            Ok(status) => {
-                if !status.success() {
-                    return Result::Err("Command failed".into());
-                } else {
-                    Ok(())
-                }
-            }
+        match task.await {
+            Ok(result) => result,
             Err(e) => {
-                return Result::Err(ProcessError::from(format!(
-                    "Failed to execute command: {}",
-                    e
-                )));
+                eprintln!("Failed to execute task: {}", e);
+                Ok(())
             }
         }
     }
 }
 
-pub struct Choice;
-
 #[async_trait]
 impl Processor<()> for Choice {
     async fn process(
@@ -378,74 +996,165 @@ impl Processor<()> for Choice {
         if file_meta
             .path
             .file_name()
-            .map(|s| s.to_string_lossy().starts_with("."))
+            .map(|s| s.to_string_lossy().starts_with('.'))
             .unwrap_or(false)
             && !context.get_config().hidden
         {
             return Ok(());
         }
 
-        // parallelize?
-        let mut tasks = vec![];
-        if let Some(ref path) = context.get_config().copy_to {
-            tasks.push(tokio::task::spawn(
-                Move::new(Operation::Copy(path.clone())).process(context, file_meta),
-            ));
-        } else if context.get_config().save {
-            tasks.push(tokio::task::spawn(Tag {}.process(context, file_meta)));
-        } else if let Some(ref path) = context.get_config().move_to {
-            tasks.push(tokio::task::spawn(
-                Move::new(Operation::Move(path.clone())).process(context, file_meta),
-            ));
-        } else if context.get_config().exec_args.is_some() {
-            tasks.push(tokio::task::spawn(Exec {}.process(context, file_meta)));
-        } else if context.get_config().delete {
-            tasks.push(tokio::task::spawn(
-                Move::new(Operation::Remove).process(context, file_meta),
-            ));
-        }
-
-        if tasks.is_empty() {
-            println!("No tasks specified");
-            return Ok(());
+        // whether a file has copies is only known once every file is seen
+        if let Some(ref duplicates) = context.duplicates {
+            if file_meta
+                .metadata
+                .as_ref()
+                .is_some_and(|metadata| metadata.kind == EntryKind::File && metadata.size > 0)
+            {
+                duplicates.lock().unwrap().push(file_meta);
+                return Ok(());
+            }
         }
 
-        for task in tasks {
-            match task.await {
-                Ok(_) => (),
-                Err(e) => {
-                    eprintln!("Failed to execute task: {}", e)
+        Self::decide(context, file_meta).await
+    }
+}
+
+/// Holds back the files when duplicates are looked for, and tags the copies when the sweep is done
+pub struct Duplicates;
+
+impl Duplicates {
+    /// The copy that is kept, if the entry is a duplicate of it
+    fn original_of(file_meta: &FileMeta) -> Option<&Path> {
+        file_meta.reasons.iter().find_map(|reason| match reason {
+            MatchReason::Duplicate(original) => Some(original.as_path()),
+            _ => None,
+        })
+    }
+
+    /// Group the files held back by their content, and go on with them
+    pub async fn run(context: Arc<MaidContext>) -> Result<(), ProcessError> {
+        let entries = match context.duplicates {
+            Some(ref duplicates) => std::mem::take(&mut *duplicates.lock().unwrap()),
+            None => return Ok(()),
+        };
+        let config = context.get_config();
+        let jobs = config.jobs.get();
+        // the hashes read from the store, the new ones are saved for the next runs
+        let saved: HashMap<PathBuf, String> = entries
+            .iter()
+            .filter_map(|file_meta| {
+                let hash = file_meta.metadata.as_ref()?.hash.clone()?;
+                Some((file_meta.path.clone(), hash))
+            })
+            .collect();
+
+        let mut copies = 0;
+        let mut decided = vec![];
+        for mut group in duplicate::group(entries, jobs).await {
+            // a dry run leaves the store as it is too
+            if config.use_mongodb && !config.dry_run {
+                Self::save_hashes(&context, &group, &saved).await;
+            }
+            duplicate::sort_by_policy(
+                config.dedupe.unwrap_or(DedupePolicy::KeepOldest),
+                &mut group,
+            );
+            let original = group[0].path.clone();
+            for (index, mut file_meta) in group.into_iter().enumerate() {
+                if index > 0 {
+                    copies += 1;
+                    file_meta
+                        .tags
+                        .get_or_insert_with(Vec::new)
+                        .insert(0, DUPLICATE_TAG.to_owned());
+                    file_meta
+                        .reasons
+                        .push(MatchReason::Duplicate(original.clone()));
                 }
+                decided.push(file_meta);
             }
         }
+        if copies > 0 {
+            println!("Found {} duplicates", copies);
+        }
+
+        stream::iter(decided)
+            .for_each_concurrent(jobs, |file_meta| {
+                let context = context.clone();
+                async move {
+                    if let Err(e) = Choice::decide(context, file_meta).await {
+                        eprintln!("Error: {}", e);
+                    }
+                }
+            })
+            .await;
         Ok(())
     }
+
+    /// Save the hashes that were not in the store, along with the saved tags
+    async fn save_hashes(
+        context: &MaidContext,
+        group: &[FileMeta],
+        saved: &HashMap<PathBuf, String>,
+    ) {
+        let store = match context.get_store() {
+            Some(store) => store,
+            None => return,
+        };
+        for file_meta in group {
+            let hash = file_meta.metadata.as_ref().and_then(|m| m.hash.as_ref());
+            if hash.is_none() || hash == saved.get(&file_meta.path) {
+                continue;
+            }
+            if let Err(e) = store.save(file_meta.clone().into()).await {
+                eprintln!(
+                    "Error: Failed to save the hash of {:?}: {}",
+                    file_meta.path, e
+                );
+            }
+        }
+    }
 }
 
 pub struct File;
 
-impl File {}
+impl File {
+    /// The signature the file starts with, if it can be read
+    async fn sniff(context: &MaidContext, path: &Path) -> Option<Signature> {
+        let header = {
+            let path = path.to_owned();
+            tokio::task::spawn_blocking(move || sniff::read_header(&path))
+                .await
+                .ok()?
+        };
+        match header {
+            Ok(header) => sniff::sniff(&context.patterns_of(path).signatures, &header).cloned(),
+            Err(e) => {
+                if context.is_debug() {
+                    println!("Cannot read {:?}: {}", path, e);
+                }
+                None
+            }
+        }
+    }
 
-#[async_trait]
-impl Processor<FileResult> for File {
-    async fn process(
-        self,
-        context: Arc<MaidContext>,
-        file_meta: FileMeta,
-    ) -> Result<FileResult, ProcessError> {
-        let path = file_meta.path;
+    /// The tags of an entry and why it got them, None for a directory with no tag of its own
+    pub async fn classify(
+        context: &MaidContext,
+        path: &Path,
+        entry_metadata: &EntryMetadata,
+    ) -> Option<(Vec<String>, Vec<MatchReason>)> {
+        let patterns = context.patterns_of(path);
         // Match types based on extensions
-        let extension = String::from(
-            path.extension()
-                .and_then(|os_str| os_str.to_str())
-                .unwrap_or("")
-                .to_ascii_lowercase(),
-        );
+        let extension = path
+            .extension()
+            .and_then(|os_str| os_str.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
 
         // Extension-based tagging
         // Find all that matches
-        let mut tags: Vec<String> = context
-            .patterns
+        let mut tags: Vec<String> = patterns
             .extensions
             .iter()
             .filter_map(|(file_type, extensions)| {
@@ -457,70 +1166,210 @@ impl Processor<FileResult> for File {
             })
             .collect();
 
-        if tags.is_empty() && path.is_dir() {
-            return Ok(FileResult::DirectoryNoTag);
+        let mut reasons = if tags.is_empty() {
+            vec![]
+        } else {
+            vec![MatchReason::Extension {
+                extension: extension.clone(),
+                tags: tags.clone(),
+            }]
+        };
+        match reasons.first() {
+            Some(reason) => context.tried(path, "extensions", true, reason),
+            None => context.tried(path, "extensions", false, "no rule matches"),
+        }
+
+        let kind = entry_metadata.kind;
+        if tags.is_empty() && kind == EntryKind::Dir {
+            return None;
         }
 
-        // TODO: if a file has no tag, and not part of a software
-        // try to read its name and content
-        // if unintelligible, tag it as garbage
+        // the content tells better when the extension is missing or does not fit
+        if context.get_config().sniff && kind == EntryKind::File {
+            match Self::sniff(context, path).await {
+                Some(signature) => {
+                    context.tried(
+                        path,
+                        "signatures",
+                        true,
+                        format_args!(
+                            "`{}` gives `{}`",
+                            signature.name,
+                            signature.tags.join("`, `")
+                        ),
+                    );
+                    if !signature.extensions.contains(&extension) {
+                        tags.clear();
+                        reasons.clear();
+                    }
+                    for tag in signature.tags.iter() {
+                        if !tags.contains(tag) {
+                            tags.push(tag.clone());
+                        }
+                    }
+                    reasons.push(MatchReason::Signature(signature.name.clone()));
+                }
+                None => context.tried(path, "signatures", false, "no signature matches"),
+            }
+        }
+
+        // files in a software are not reached, as the software is tagged as a whole.
+        // the tag goes last, so the entry is still filed by what it is
+        match patterns.garbage.check(path, entry_metadata).await {
+            Some(reason) => {
+                context.tried(path, "garbage", true, &reason);
+                tags.push(GARBAGE_TAG.to_owned());
+                reasons.push(reason);
+            }
+            None => context.tried(path, "garbage", false, "no rule matches"),
+        }
 
         if tags.is_empty() {
             tags.push("misc".into());
+            reasons.push(MatchReason::Fallback);
         }
+        Some((tags, reasons))
+    }
+}
 
-        // match with given tags and dispatch
-        // if no tags specified then dispatch them all
-        if file_meta.tags.is_none() || file_meta.tags.as_ref().unwrap().is_empty() {
-            // when dispatching, tags means what kind of file it is
-            Choice {}
-                .process(
-                    context,
-                    FileMeta {
-                        path: path,
-                        tags: Some(tags),
-                        last_modified: None,
-                    },
-                )
-                .await?;
-            return Ok(FileResult::Ok(vec![]));
-        }
-
-        for tag in tags.iter() {
-            if let Some(ref filter_tags) = file_meta.tags {
-                if filter_tags.contains(tag) {
-                    Choice {}
-                        .process(
-                            context,
-                            FileMeta {
-                                path: path,
-                                tags: Some(tags),
-                                last_modified: None,
-                            },
-                        )
-                        .await?;
-                }
+#[async_trait]
+impl Processor<FileResult> for File {
+    async fn process(
+        self,
+        context: Arc<MaidContext>,
+        file_meta: FileMeta,
+    ) -> Result<FileResult, ProcessError> {
+        let path = file_meta.path;
+        let entry_metadata = match file_meta.metadata {
+            Some(metadata) => metadata,
+            None => tokio::fs::symlink_metadata(&path)
+                .await
+                .map(|metadata| EntryMetadata::from(&metadata))
+                .map_err(|e| ProcessError::from(format!("Failed to read {:?}: {}", path, e)))?,
+        };
+        let (tags, reasons) = match Self::classify(&context, &path, &entry_metadata).await {
+            Some(classified) => classified,
+            None => return Ok(FileResult::DirectoryNoTag),
+        };
+
+        // what was read during the walk is all there is to a file, a directory is summed up
+        let metadata = match entry_metadata.kind {
+            EntryKind::Dir => {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    fileop::complete_metadata(&path, entry_metadata)
+                })
+                .await
+                .map_err(|e| e.to_string())?
             }
-            break;
-        }
-        Ok(FileResult::Ok(vec![]))
+            _ => entry_metadata,
+        };
+        // the tags are filtered when dispatching
+        Choice {}
+            .process(
+                context,
+                FileMeta {
+                    path,
+                    tags: Some(tags),
+                    metadata: Some(metadata),
+                    reasons,
+                    members: vec![],
+                },
+            )
+            .await?;
+        Ok(FileResult::Ok)
     }
 }
 
 pub struct Directory;
 
 impl Directory {
+    /// Run a part of the walk on its own task if a walker is free, otherwise in place.
+    /// This bounds the directories in flight without waiting on a walker while holding one.
+    async fn fork<F>(context: &MaidContext, task: F, tasks: &mut Vec<JoinHandle<()>>)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match context.walkers.clone().try_acquire_owned() {
+            Ok(permit) => tasks.push(tokio::spawn(async move {
+                task.await;
+                drop(permit);
+            })),
+            Err(_) => task.await,
+        }
+    }
+
     /// Directly tagging a directory
-    async fn handle(self, context: Arc<MaidContext>, file_meta: FileMeta) -> () {
-        match (Choice {}.process(context, file_meta).await) {
+    async fn handle(self, context: Arc<MaidContext>, mut file_meta: FileMeta) -> () {
+        let result = match read_metadata(&file_meta.path).await {
+            Ok(metadata) => {
+                file_meta.metadata = Some(metadata);
+                Choice {}.process(context, file_meta).await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
             Ok(_) => (),
             Err(e) => println!("Error: {}", e),
         }
     }
 
+    /// Tagging a group of files as one entry. A series is tagged by its first file,
+    /// a split archive by its whole name, or by the special file its first volume is.
+    async fn group(
+        self,
+        context: Arc<MaidContext>,
+        path: PathBuf,
+        kind: EntryKind,
+        members: Vec<PathBuf>,
+        reason: MatchReason,
+    ) -> () {
+        let result = async {
+            let mut metadata = vec![];
+            for member in members.iter() {
+                metadata.push(read_metadata(member).await?);
+            }
+            let combined = EntryMetadata::combined(kind, &metadata);
+            let (tags, mut reasons) = match self.match_special_file(&context, &members[0]) {
+                Some((tags, reason)) => Some((tags, vec![reason])),
+                None if kind == EntryKind::Series => {
+                    File::classify(&context, &members[0], &metadata[0]).await
+                }
+                None => File::classify(&context, &path, &combined).await,
+            }
+            .unwrap_or_default();
+            reasons.push(reason);
+            Choice {}
+                .process(
+                    context.clone(),
+                    FileMeta {
+                        path,
+                        tags: Some(tags),
+                        metadata: Some(combined),
+                        reasons,
+                        members,
+                    },
+                )
+                .await
+        }
+        .await;
+        if let Err(e) = result {
+            println!("Error: {}", e);
+        }
+    }
+
     /// Calls another dispatcher to process a directory or file
     async fn recurse(self, context: Arc<MaidContext>, entry: DirEntry) -> () {
         let path = entry.path();
+        // links are not followed, so the walk cannot loop
+        let metadata = match entry.metadata().await {
+            Ok(metadata) => EntryMetadata::from(&metadata),
+            Err(e) => {
+                println!("Error: {}", e);
+                return;
+            }
+        };
+        let is_dir = metadata.kind == EntryKind::Dir;
         // try to match the folder name with other tags, if it fails, continue
         match File
             .process(
@@ -528,24 +1377,28 @@ impl Directory {
                 FileMeta {
                     path: entry.path().clone(),
                     tags: None,
-                    last_modified: None,
+                    metadata: Some(metadata),
+                    reasons: vec![],
+                    members: vec![],
                 },
             )
             .await
         {
             Ok(FileResult::DirectoryNoTag) => (),
-            Ok(FileResult::Ok(_)) => return,
+            Ok(FileResult::Ok) => return,
             Err(e) => println!("Error: {}", e),
         }
 
-        if path.is_dir() {
+        if is_dir {
             match Directory
                 .process(
                     context.clone(),
                     FileMeta {
-                        path: path,
+                        path,
                         tags: None,
-                        last_modified: None,
+                        metadata: None,
+                        reasons: vec![],
+                        members: vec![],
                     },
                 )
                 .await
@@ -556,18 +1409,32 @@ impl Directory {
         }
     }
 
-    fn match_special_file(&self, context: &Arc<MaidContext>, path: &Path) -> Option<Vec<String>> {
-        context
-            .patterns
-            .filenames_re
-            .iter()
-            .find_map(|(file_tags, filename_pattern)| {
-                if filename_pattern.is_match(path.file_name().unwrap().to_str().unwrap()) {
-                    Some(file_tags.clone())
-                } else {
-                    None
-                }
-            })
+    fn match_special_file(
+        &self,
+        context: &Arc<MaidContext>,
+        path: &Path,
+    ) -> Option<(Vec<String>, MatchReason)> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        for (file_tags, filename_pattern) in context.patterns_of(path).filenames_re.iter() {
+            let pattern = filename_pattern.as_str();
+            if filename_pattern.is_match(&name) {
+                context.tried(
+                    path,
+                    "filenames",
+                    true,
+                    format_args!("`{}` gives `{}`", pattern, file_tags.join("`, `")),
+                );
+                return Some((
+                    file_tags.clone(),
+                    MatchReason::Filename {
+                        pattern: pattern.to_owned(),
+                        tags: file_tags.clone(),
+                    },
+                ));
+            }
+            context.tried(path, "filenames", false, format_args!("`{}`", pattern));
+        }
+        None
     }
 }
 
@@ -589,9 +1456,8 @@ impl Processor<()> for Directory {
             }
         };
 
-        // first pass to filter out typical directories and special files
+        // first pass to list the directory, and filter out typical directories
         let mut filtered_entries = vec![];
-        let mut file_tag_tasks: Vec<tokio::task::JoinHandle<_>> = vec![];
         loop {
             // IO error in listing the directory
             let result = entries.next_entry().await;
@@ -606,68 +1472,247 @@ impl Processor<()> for Directory {
                 break;
             }
 
-            let entry = result.unwrap();
-            let path = entry.path();
-
-            // if there is a file typical of a kind of directory, tag the directory and stop here
-            // typical means there is no ambiguity (but it should be able to have multiple tags)
-            // so there is no need to continue
-            // TODO: support multiple tags for typical
-            let match_result: Option<Vec<String>> =
-                context.patterns.typical_files_re.iter().find_map(
-                    |(file_tag, filename_patterns)| {
-                        if filename_patterns.is_match(path.file_name().unwrap().to_str().unwrap()) {
-                            return Some(vec![file_tag.clone()]);
-                        }
-                        None
-                    },
-                );
+            // all the entries are listed first, so the typical files are found in any order
+            filtered_entries.push(result.unwrap());
+        }
+        filtered_entries.sort_by_key(|entry| entry.file_name());
 
-            if let Some(tags) = match_result {
-                // if such typical file is found, handle the parent directory
-                // and return
-                // as this is the only file that matters, we pass up its error
-                // with multiple files we ignore them
-                return Choice {}
-                    .process(
-                        context.clone(),
-                        FileMeta {
-                            path: directory,
-                            tags: Some(tags),
-                            last_modified: None,
-                        },
-                    )
-                    .await;
+        // the configuration of a directory is not swept, it is put over the rules for its entries
+        if let Some(index) = filtered_entries
+            .iter()
+            .position(|entry| entry.file_name() == layer::LOCAL_CONFIG)
+        {
+            filtered_entries.remove(index);
+            if let Some(config_path) = layer::local_config(&directory) {
+                context.add_local(&directory, &[config_path]);
             }
+        }
+        let patterns = context.patterns_in(&directory);
 
-            // Find out if it is a special file
-            // Special files are not part of a directory, and meaningful even when alone
-            // So tagging/moving them sooner or later does not matter
-
-            // if it is a special file, add its handling to the tasks
-            if let Some(special_tags) = self.match_special_file(&context, &path) {
-                file_tag_tasks.push(tokio::spawn(Self {}.handle(
+        // if there are files typical of a kind of directory, tag the directory and stop here
+        // typical means there is no ambiguity, so there is no need to continue
+        let names: Vec<(String, PathBuf)> = filtered_entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.file_name().to_string_lossy().into_owned(),
+                    entry.path(),
+                )
+            })
+            .collect();
+        let typical = typical::find_typical(&patterns.typical_files, &names, |rule, why| {
+            context.tried(
+                &directory,
+                "typical_files",
+                false,
+                format_args!("`{}` (priority {}): {}", rule.name, rule.priority, why),
+            )
+        });
+        if let Some((rule, reasons)) = typical {
+            context.tried(
+                &directory,
+                "typical_files",
+                true,
+                format_args!(
+                    "`{}` (priority {}) gives `{}`",
+                    rule.name,
+                    rule.priority,
+                    rule.tags.join("`, `")
+                ),
+            );
+            if context.is_debug() {
+                println!("Typical files {} found in {:?}", rule.name, directory);
+            }
+            // as these are the only files that matter, we pass up its error
+            // with multiple files we ignore them
+            let metadata = read_metadata(&directory).await?;
+            return Choice {}
+                .process(
                     context.clone(),
                     FileMeta {
-                        path: path,
-                        tags: Some(special_tags),
-                        last_modified: None,
+                        path: directory,
+                        tags: Some(rule.tags.clone()),
+                        metadata: Some(metadata),
+                        reasons,
+                        members: vec![],
                     },
-                )));
-                // no need to process it again
-                // skip to next file
+                )
+                .await;
+        }
+
+        let mut file_tag_tasks = vec![];
+
+        let mut files = vec![];
+        for entry in filtered_entries.iter() {
+            if entry
+                .file_type()
+                .await
+                .is_ok_and(|file_type| file_type.is_file())
+            {
+                files.push(entry.path());
+            }
+        }
+
+        // the volumes of a split archive are one entry, before they are taken as special files
+        // or a series, so they all get the same tags and are never parted
+        let sets = volumes::find_volume_sets(&files);
+        if context.trace.is_some() {
+            for file in files.iter() {
+                match sets.iter().find(|set| set.members.contains(file)) {
+                    Some(set) => context.tried(
+                        file,
+                        "volumes",
+                        true,
+                        format_args!(
+                            "a volume of {:?} with {} volumes",
+                            directory.join(&set.name),
+                            set.members.len()
+                        ),
+                    ),
+                    None => {
+                        context.tried(file, "volumes", false, "not a volume of a split archive")
+                    }
+                }
+            }
+        }
+        let grouped: HashSet<PathBuf> = sets
+            .iter()
+            .flat_map(|set| set.members.iter().cloned())
+            .collect();
+        filtered_entries.retain(|entry| !grouped.contains(&entry.path()));
+        files.retain(|file| !grouped.contains(file));
+        for set in sets {
+            let path = directory.join(&set.name);
+            if let Some(ref trace) = context.trace {
+                trace.grouped(&path, &set.members);
+            }
+            if !context.follows(&path) {
                 continue;
             }
+            let reason = MatchReason::Volumes(set.members.len());
+            Self::fork(
+                &context,
+                Self {}.group(
+                    context.clone(),
+                    path,
+                    EntryKind::Volumes,
+                    set.members,
+                    reason,
+                ),
+                &mut file_tag_tasks,
+            )
+            .await;
+        }
+
+        // Find out if it is a special file
+        // Special files are not part of a directory, and meaningful even when alone
+        // So tagging/moving them sooner or later does not matter
+        let mut special_files = vec![];
+        filtered_entries.retain(|entry| {
+            let path = entry.path();
+            match self.match_special_file(&context, &path) {
+                Some((special_tags, reason)) => {
+                    special_files.push(FileMeta {
+                        path,
+                        tags: Some(special_tags),
+                        metadata: None,
+                        reasons: vec![reason],
+                        members: vec![],
+                    });
+                    false
+                }
+                None => true,
+            }
+        });
 
-            // otherwise proceed to add the files to the list of second pass
-            filtered_entries.push(entry);
+        // siblings with names in a row are kept together, as one entry
+        if !patterns.series.is_empty() {
+            files.retain(|file| !special_files.iter().any(|special| special.path == *file));
+            let found = series::find_series(&patterns.series, &directory, &files);
+            if context.trace.is_some() {
+                for file in files.iter() {
+                    let rules = &patterns.series;
+                    match series::series_key(rules, file) {
+                        Some((key, _)) => {
+                            let rule = &rules[key.rule];
+                            match found.iter().find(|series| series.members.contains(file)) {
+                                Some(series) => context.tried(
+                                    file,
+                                    "series",
+                                    true,
+                                    format_args!(
+                                        "`{}`: {} in a row as {:?}",
+                                        rule.name,
+                                        series.members.len(),
+                                        series.name
+                                    ),
+                                ),
+                                None => context.tried(
+                                    file,
+                                    "series",
+                                    false,
+                                    format_args!(
+                                        "`{}`: fewer than {} in a row with the same extension",
+                                        rule.name, rule.min_count
+                                    ),
+                                ),
+                            }
+                        }
+                        None => context.tried(file, "series", false, "the name matches no rule"),
+                    }
+                }
+            }
+            let grouped: HashSet<PathBuf> = found
+                .iter()
+                .flat_map(|series| series.members.iter().cloned())
+                .collect();
+            filtered_entries.retain(|entry| !grouped.contains(&entry.path()));
+            for series in found {
+                let path = series.members[0].clone();
+                if let Some(ref trace) = context.trace {
+                    trace.grouped(&path, &series.members);
+                }
+                if !context.follows(&path) {
+                    continue;
+                }
+                let reason = MatchReason::Series(series.rule);
+                Self::fork(
+                    &context,
+                    Self {}.group(
+                        context.clone(),
+                        path,
+                        EntryKind::Series,
+                        series.members,
+                        reason,
+                    ),
+                    &mut file_tag_tasks,
+                )
+                .await;
+            }
         }
 
-        file_tag_tasks.extend(
-            filtered_entries
-                .into_iter()
-                .map(|entry| tokio::spawn(Self {}.recurse(context.clone(), entry))),
-        );
+        for file_meta in special_files {
+            if !context.follows(&file_meta.path) {
+                continue;
+            }
+            Self::fork(
+                &context,
+                Self {}.handle(context.clone(), file_meta),
+                &mut file_tag_tasks,
+            )
+            .await;
+        }
+        for entry in filtered_entries {
+            if !context.follows(&entry.path()) {
+                continue;
+            }
+            Self::fork(
+                &context,
+                Self {}.recurse(context.clone(), entry),
+                &mut file_tag_tasks,
+            )
+            .await;
+        }
 
         for task in file_tag_tasks {
             if let Err(e) = task.await {
@@ -678,3 +1723,61 @@ impl Processor<()> for Directory {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_for_cmd_like_command_line_to_argv() {
+        let quote = |arg: &str| cmd_quote(OsStr::new(arg));
+        assert_eq!(quote(r"C:\dir\a b.txt"), r#""C:\dir\a b.txt""#);
+        assert_eq!(quote(r"C:\dir\"), r#""C:\dir\\""#);
+        assert_eq!(quote(r#"say "hi""#), r#""say \"hi\"""#);
+        assert_eq!(quote(r#"a\"b"#), r#""a\\\"b""#);
+        assert_eq!(quote(r"\\server\share"), r#""\\server\share""#);
+    }
+
+    #[test]
+    fn quoted_for_posix_shells() {
+        assert_eq!(posix_quote(OsStr::new("it's")), r"'it'\''s'");
+        let line = Exec::shell_line(
+            &["cat".into(), "a b".into(), "|".into(), "wc".into()],
+            posix_quote,
+        );
+        assert_eq!(line, "'cat' 'a b' | 'wc'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_that_are_not_utf8_are_passed_as_they_are() {
+        use std::os::unix::ffi::OsStrExt;
+        let arg = OsStr::from_bytes(b"/a/\xff'.txt");
+        assert_eq!(posix_quote(arg).as_bytes(), b"'/a/\xff'\\''.txt'");
+        assert_eq!(cmd_quote(arg).as_bytes(), b"\"/a/\xff'.txt\"");
+    }
+
+    #[test]
+    fn numbered_names_taken_meanwhile_are_skipped() {
+        let directory = std::env::temp_dir().join(format!("maid-place-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("new.jpg");
+        let target = directory.join("photo.jpg");
+        for path in [&source, &target, &directory.join("photo_1.jpg")] {
+            fs::write(path, path.to_string_lossy().as_bytes()).unwrap();
+        }
+
+        let placed = Move::place_numbered(&source, &target, fileop::move_path).unwrap();
+        assert_eq!(placed, directory.join("photo_2.jpg"));
+        assert_eq!(
+            fs::read_to_string(&placed).unwrap(),
+            source.to_string_lossy()
+        );
+        assert_eq!(
+            fs::read_to_string(directory.join("photo_1.jpg")).unwrap(),
+            directory.join("photo_1.jpg").to_string_lossy()
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
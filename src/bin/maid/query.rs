@@ -0,0 +1,394 @@
+use crate::config::{MaidConfig, Patterns};
+use crate::datatype::EntryMetadata;
+use crate::filter::{self, Bound, Comparison, Field};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+
+/// Which entries to sweep, like `(image | video) & stale & size > 100M`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Query {
+    /// The entry has the tag
+    Tag(String),
+    Bound(Bound),
+    Not(Box<Query>),
+    /// True when empty
+    And(Vec<Query>),
+    /// False when empty
+    Or(Vec<Query>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Compare(Comparison),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{}`", word),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+            Token::And => write!(f, "`&`"),
+            Token::Or => write!(f, "`|`"),
+            Token::Not => write!(f, "`!`"),
+            Token::Compare(comparison) => write!(f, "`{}`", comparison.sql_operator()),
+        }
+    }
+}
+
+const OPERATOR_CHARS: &str = "()&|!<>=";
+
+fn tokenize(query: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = query.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, length) = match c {
+            '(' => (Token::Open, 1),
+            ')' => (Token::Close, 1),
+            '&' => (Token::And, 1),
+            '|' => (Token::Or, 1),
+            '!' => (Token::Not, 1),
+            '<' | '>' | '=' => {
+                let operator: String = rest.chars().take_while(|c| "<>=".contains(*c)).collect();
+                let comparison = match operator.as_str() {
+                    "<" => Comparison::Less,
+                    "<=" => Comparison::LessOrEqual,
+                    ">" => Comparison::Greater,
+                    ">=" => Comparison::GreaterOrEqual,
+                    "=" | "==" => Comparison::Equal,
+                    _ => return Err(format!("Unknown operator {:?}", operator)),
+                };
+                (Token::Compare(comparison), operator.len())
+            }
+            _ => {
+                let word: String = rest
+                    .chars()
+                    .take_while(|c| !c.is_whitespace() && !OPERATOR_CHARS.contains(*c))
+                    .collect();
+                let length = word.len();
+                (Token::Word(word), length)
+            }
+        };
+        tokens.push(token);
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Query, String> {
+        let mut operands = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            operands.push(self.and()?);
+        }
+        Ok(Query::or(operands))
+    }
+
+    fn and(&mut self) -> Result<Query, String> {
+        let mut operands = vec![self.unary()?];
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            operands.push(self.unary()?);
+        }
+        Ok(Query::and(operands))
+    }
+
+    fn unary(&mut self) -> Result<Query, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Query::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let query = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err("Missing `)`".to_owned()),
+                }
+            }
+            Some(Token::Word(word)) => match self.peek() {
+                Some(&Token::Compare(comparison)) => {
+                    self.next();
+                    match self.next() {
+                        Some(Token::Word(value)) => comparison_of(&word, comparison, &value),
+                        _ => Err(format!("Missing a value to compare {} with", word)),
+                    }
+                }
+                _ => Ok(Query::Tag(word)),
+            },
+            Some(token) => Err(format!("Unexpected {}", token)),
+            None => Err("Unexpected end of the query".to_owned()),
+        }
+    }
+}
+
+fn comparison_of(field: &str, comparison: Comparison, value: &str) -> Result<Query, String> {
+    let (field, comparison, value) = match field {
+        "size" => (Field::Size, comparison, filter::parse_size(value)?),
+        "modified" => (Field::LastModified, comparison, filter::parse_time(value)?),
+        "accessed" => (Field::LastAccessed, comparison, filter::parse_time(value)?),
+        // older is smaller in time
+        "age" => (
+            Field::LastModified,
+            comparison.reversed(),
+            filter::parse_time(value)?,
+        ),
+        _ => {
+            return Err(format!(
+                "Unknown field {:?}, expected one of size, modified, accessed, age",
+                field
+            ))
+        }
+    };
+    Ok(Query::Bound(Bound {
+        field,
+        comparison,
+        value,
+    }))
+}
+
+impl Query {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(query)?,
+            position: 0,
+        };
+        if parser.peek().is_none() {
+            return Ok(Query::And(vec![]));
+        }
+        let parsed = parser.or()?;
+        match parser.next() {
+            None => Ok(parsed),
+            Some(token) => Err(format!("Unexpected {}", token)),
+        }
+    }
+
+    /// For clap to parse arguments into queries
+    pub fn parse_arg(query: &str) -> Result<Self, String> {
+        Self::parse(query)
+    }
+
+    fn and(mut operands: Vec<Query>) -> Self {
+        if operands.len() == 1 {
+            operands.pop().unwrap()
+        } else {
+            Query::And(operands)
+        }
+    }
+
+    fn or(mut operands: Vec<Query>) -> Self {
+        if operands.len() == 1 {
+            operands.pop().unwrap()
+        } else {
+            Query::Or(operands)
+        }
+    }
+
+    /// Everything asked for on the command line, with the tags expanded to their synonyms
+    pub fn from_config(config: &MaidConfig, patterns: &Patterns) -> Self {
+        let mut operands = vec![];
+        if let Some(ref tags) = config.tags {
+            let tags = tags.iter().cloned().map(Query::Tag).collect();
+            operands.push(if config.all_tags {
+                Query::And(tags)
+            } else {
+                Query::Or(tags)
+            });
+        }
+        operands.extend(
+            filter::bounds(
+                config.older_than,
+                config.newer_than,
+                config.accessed_before,
+                config.larger_than,
+                config.smaller_than,
+            )
+            .into_iter()
+            .map(Query::Bound),
+        );
+        operands.extend(config.query.iter().cloned());
+        Query::and(operands).expand(&patterns.synonyms)
+    }
+
+    /// Replace each tag having synonyms with any of them
+    pub fn expand(self, synonyms: &HashMap<String, HashSet<String>>) -> Self {
+        self.map_tags(&|tag| match synonyms.get(&tag) {
+            Some(synonyms) => Query::or(synonyms.iter().cloned().map(Query::Tag).collect()),
+            None => Query::Tag(tag),
+        })
+    }
+
+    /// Replace the age tags with their limits, as they are not saved
+    pub fn resolve_age_tags(self, patterns: &Patterns) -> Self {
+        self.map_tags(&|tag| match patterns
+            .age_tags
+            .iter()
+            .find(|(age_tag, _)| *age_tag == tag)
+        {
+            Some((_, bounds)) => Query::and(bounds.iter().cloned().map(Query::Bound).collect()),
+            None => Query::Tag(tag),
+        })
+    }
+
+    fn map_tags<F>(self, f: &F) -> Self
+    where
+        F: Fn(String) -> Query,
+    {
+        match self {
+            Query::Tag(tag) => f(tag),
+            Query::Bound(bound) => Query::Bound(bound),
+            Query::Not(query) => Query::Not(Box::new(query.map_tags(f))),
+            Query::And(queries) => Query::And(queries.into_iter().map(|q| q.map_tags(f)).collect()),
+            Query::Or(queries) => Query::Or(queries.into_iter().map(|q| q.map_tags(f)).collect()),
+        }
+    }
+
+    /// Whether the tag is looked for anywhere in the query
+    pub fn has_tag(&self, tag: &str) -> bool {
+        match self {
+            Query::Tag(name) => name == tag,
+            Query::Bound(_) => false,
+            Query::Not(query) => query.has_tag(tag),
+            Query::And(queries) | Query::Or(queries) => {
+                queries.iter().any(|query| query.has_tag(tag))
+            }
+        }
+    }
+
+    /// An entry without metadata is never within a bound
+    pub fn matches(&self, tags: &[String], metadata: Option<&EntryMetadata>) -> bool {
+        match self {
+            Query::Tag(tag) => tags.contains(tag),
+            Query::Bound(bound) => metadata.is_some_and(|metadata| bound.matches(metadata)),
+            Query::Not(query) => !query.matches(tags, metadata),
+            Query::And(queries) => queries.iter().all(|query| query.matches(tags, metadata)),
+            Query::Or(queries) => queries.iter().any(|query| query.matches(tags, metadata)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str) -> Query {
+        Query::Tag(name.to_owned())
+    }
+
+    fn tags(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            Query::parse("a | b & c").unwrap(),
+            Query::Or(vec![tag("a"), Query::And(vec![tag("b"), tag("c")])])
+        );
+        assert_eq!(
+            Query::parse("a & b | c").unwrap(),
+            Query::Or(vec![Query::And(vec![tag("a"), tag("b")]), tag("c")])
+        );
+    }
+
+    #[test]
+    fn parentheses_group_first() {
+        assert_eq!(
+            Query::parse("(a | b) & c").unwrap(),
+            Query::And(vec![Query::Or(vec![tag("a"), tag("b")]), tag("c")])
+        );
+        assert_eq!(
+            Query::parse("((a))").unwrap(),
+            tag("a"),
+            "parentheses around one operand add nothing"
+        );
+    }
+
+    #[test]
+    fn not_applies_to_the_next_operand() {
+        assert_eq!(
+            Query::parse("!a & b").unwrap(),
+            Query::And(vec![Query::Not(Box::new(tag("a"))), tag("b")])
+        );
+        assert_eq!(
+            Query::parse("!(a | b)").unwrap(),
+            Query::Not(Box::new(Query::Or(vec![tag("a"), tag("b")])))
+        );
+    }
+
+    #[test]
+    fn comparisons_are_operands() {
+        assert_eq!(
+            Query::parse("image & size >= 1K").unwrap(),
+            Query::And(vec![
+                tag("image"),
+                Query::Bound(Bound {
+                    field: Field::Size,
+                    comparison: Comparison::GreaterOrEqual,
+                    value: 1024,
+                }),
+            ])
+        );
+        // older is earlier
+        match Query::parse("age > 1d").unwrap() {
+            Query::Bound(bound) => {
+                assert_eq!(bound.field, Field::LastModified);
+                assert_eq!(bound.comparison, Comparison::Less);
+            }
+            query => panic!("expected a bound, got {:?}", query),
+        }
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        let query = Query::parse("  ").unwrap();
+        assert_eq!(query, Query::And(vec![]));
+        assert!(query.matches(&[], None));
+    }
+
+    #[test]
+    fn malformed_queries_are_errors() {
+        for query in [
+            "(a | b",
+            "a b",
+            "a &",
+            "& a",
+            "a )",
+            "size >",
+            "colour > 1",
+            "size => 1K",
+            "size > 99999999999T",
+        ] {
+            assert!(Query::parse(query).is_err(), "{:?} is accepted", query);
+        }
+    }
+
+    #[test]
+    fn matches_by_tags() {
+        let query = Query::parse("(image | video) & !stale").unwrap();
+        assert!(query.matches(&tags(&["video"]), None));
+        assert!(!query.matches(&tags(&["video", "stale"]), None));
+        assert!(!query.matches(&tags(&["text"]), None));
+        // no metadata is never within a bound
+        assert!(!Query::parse("size > 1").unwrap().matches(&[], None));
+    }
+}
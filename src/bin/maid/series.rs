@@ -0,0 +1,205 @@
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Names in a row, like `page01.jpg` or `S01E02.mkv`. The `prefix` group is what they have
+/// in common, and the numbers of the `number` group, if any, have to follow each other.
+#[derive(Debug)]
+pub struct SeriesRule {
+    pub name: String,
+    pub pattern: Regex,
+    /// How many siblings it takes to make a series
+    pub min_count: usize,
+}
+
+/// Siblings found to be in a row. The series goes by the path of its first file,
+/// which no other entry has.
+#[derive(Debug)]
+pub struct Series {
+    /// The name of the folder they are kept together in
+    pub name: String,
+    /// The rule they were found by
+    pub rule: String,
+    /// Sorted by name
+    pub members: Vec<PathBuf>,
+}
+
+/// The name of the folder for a prefix, the directory they are in if nothing is in common
+fn folder_name(prefix: &str, directory: &Path) -> String {
+    let prefix = prefix.trim_end_matches(|c: char| c.is_whitespace() || "._-".contains(c));
+    if !prefix.is_empty() {
+        return prefix.to_owned();
+    }
+    directory
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "series".to_owned())
+}
+
+/// What the files of a series have in common: the first rule matching their names,
+/// the prefix it finds and the extension, so they are all of the same kind
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SeriesKey {
+    pub rule: usize,
+    pub prefix: String,
+    pub extension: String,
+}
+
+/// The key of a file, and its number if the rule has a `number` group
+pub fn series_key(rules: &[SeriesRule], file: &Path) -> Option<(SeriesKey, Option<u64>)> {
+    let name = file.file_name()?.to_str()?;
+    let extension = file
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    rules.iter().enumerate().find_map(|(index, rule)| {
+        let captures = rule.pattern.captures(name)?;
+        let prefix = captures.name("prefix").map_or("", |prefix| prefix.as_str());
+        let number = captures
+            .name("number")
+            .and_then(|number| number.as_str().parse().ok());
+        let key = SeriesKey {
+            rule: index,
+            prefix: prefix.to_owned(),
+            extension: extension.clone(),
+        };
+        Some((key, number))
+    })
+}
+
+/// The name of the folder for the series a file is the first of
+pub fn folder_of(rules: &[SeriesRule], first: &Path) -> Option<String> {
+    let (key, _) = series_key(rules, first)?;
+    Some(folder_name(
+        &key.prefix,
+        first.parent().unwrap_or(Path::new("")),
+    ))
+}
+
+/// Split files into runs of numbers following each other, or keep them together
+/// when they have no number
+fn runs(mut members: Vec<(Option<u64>, PathBuf)>) -> Vec<Vec<PathBuf>> {
+    if members.iter().any(|(number, _)| number.is_none()) {
+        return vec![members.into_iter().map(|(_, member)| member).collect()];
+    }
+    members.sort();
+    let mut runs: Vec<Vec<PathBuf>> = vec![];
+    let mut last = None;
+    for (number, member) in members {
+        match (last, number) {
+            (Some(last), Some(number)) if last + 1 == number => {
+                runs.last_mut().unwrap().push(member)
+            }
+            _ => runs.push(vec![member]),
+        }
+        last = number;
+    }
+    runs
+}
+
+/// Group the files of a directory by the first rule matching their name, the prefix it finds
+/// and their extension, keeping the ones with numbers in a row
+pub fn find_series(rules: &[SeriesRule], directory: &Path, files: &[PathBuf]) -> Vec<Series> {
+    let mut groups: BTreeMap<SeriesKey, Vec<(Option<u64>, PathBuf)>> = BTreeMap::new();
+    for file in files {
+        if let Some((key, number)) = series_key(rules, file) {
+            groups.entry(key).or_default().push((number, file.clone()));
+        }
+    }
+
+    groups
+        .into_iter()
+        .flat_map(|(key, members)| {
+            runs(members)
+                .into_iter()
+                .map(move |members| (key.clone(), members))
+        })
+        .filter(|(key, members)| members.len() >= rules[key.rule].min_count)
+        .map(|(key, mut members)| {
+            members.sort();
+            Series {
+                name: folder_name(&key.prefix, directory),
+                rule: rules[key.rule].name.clone(),
+                members,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> Vec<SeriesRule> {
+        let rule = |name: &str, pattern: &str, min_count| SeriesRule {
+            name: name.to_owned(),
+            pattern: Regex::new(pattern).unwrap(),
+            min_count,
+        };
+        vec![
+            rule(
+                "episodes",
+                r"^(?P<prefix>.+?)[ ._-]*[Ss]\d{1,2}[Ee]\d{1,3}",
+                2,
+            ),
+            rule(
+                "numbered",
+                r"^(?P<prefix>\D*[^\d\s._-])[ ._-]*(?P<number>\d{1,4})\.[^.]+$",
+                3,
+            ),
+        ]
+    }
+
+    fn files(names: &[&str]) -> Vec<PathBuf> {
+        names
+            .iter()
+            .map(|name| Path::new("/d").join(name))
+            .collect()
+    }
+
+    fn found(names: &[&str]) -> Vec<(String, Vec<PathBuf>)> {
+        find_series(&rules(), Path::new("/d"), &files(names))
+            .into_iter()
+            .map(|series| (series.name, series.members))
+            .collect()
+    }
+
+    #[test]
+    fn numbers_in_a_row() {
+        assert_eq!(
+            found(&["page_03.jpg", "page_01.jpg", "page_02.jpg", "page_07.jpg"]),
+            [(
+                "page".to_owned(),
+                files(&["page_01.jpg", "page_02.jpg", "page_03.jpg"])
+            )]
+        );
+        // two apart are not enough for the rule
+        assert!(found(&["page_01.jpg", "page_02.jpg", "page_04.jpg", "page_05.jpg"]).is_empty());
+    }
+
+    #[test]
+    fn same_extension_only() {
+        assert!(found(&["scan1.jpg", "scan2.png", "scan3.jpg"]).is_empty());
+        assert_eq!(found(&["scan1.png", "scan2.png", "scan3.png"]).len(), 1);
+    }
+
+    #[test]
+    fn first_rule_matching_wins() {
+        let series = found(&["Show.S01E01.mkv", "Show.S01E02.mkv", "other.txt"]);
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].0, "Show");
+        let (key, number) = series_key(&rules(), Path::new("/d/Show.S01E02.mkv")).unwrap();
+        assert_eq!((key.rule, number), (0, None));
+        assert!(series_key(&rules(), Path::new("/d/2023.txt")).is_none());
+    }
+
+    #[test]
+    fn folder_after_the_prefix_or_the_directory() {
+        assert_eq!(
+            folder_of(&rules(), Path::new("/d/page_01.jpg")).as_deref(),
+            Some("page")
+        );
+        assert_eq!(folder_name("  ", Path::new("/photos/trip")), "trip");
+        assert_eq!(folder_name("Show - ", Path::new("/d")), "Show");
+    }
+}
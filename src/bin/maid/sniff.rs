@@ -0,0 +1,319 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// How much of a file is read to find its signature
+pub const HEADER_SIZE: usize = 4096;
+
+/// The name, the tags, the offset, the magic and the extensions of a signature
+type SignatureRow = (
+    &'static str,
+    &'static [&'static str],
+    usize,
+    &'static str,
+    &'static [&'static str],
+);
+
+/// Magic numbers at the start of files, they are tried after the ones in the config.
+/// Each has a name, the tags, where the magic is, the magic in hex with `??` for any byte,
+/// and the extensions the format goes by.
+const BUILTIN_SIGNATURES: &[SignatureRow] = &[
+    (
+        "PNG",
+        &["image"],
+        0,
+        "89 50 4E 47 0D 0A 1A 0A",
+        &["png", "apng"],
+    ),
+    (
+        "JPEG",
+        &["image"],
+        0,
+        "FF D8 FF",
+        &["jpg", "jpeg", "jpe", "jfif"],
+    ),
+    ("GIF", &["image"], 0, "47 49 46 38", &["gif"]),
+    (
+        "WebP",
+        &["image"],
+        0,
+        "52 49 46 46 ?? ?? ?? ?? 57 45 42 50",
+        &["webp"],
+    ),
+    (
+        "TIFF",
+        &["image"],
+        0,
+        "49 49 2A 00",
+        &["tif", "tiff", "dng", "nef"],
+    ),
+    (
+        "TIFF",
+        &["image"],
+        0,
+        "4D 4D 00 2A",
+        &["tif", "tiff", "dng", "nef"],
+    ),
+    ("PDF", &["text"], 0, "25 50 44 46", &["pdf", "ai"]),
+    (
+        "ZIP",
+        &["compressed"],
+        0,
+        "50 4B 03 04",
+        &[
+            "zip", "jar", "apk", "ipa", "whl", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub",
+            "xpi", "cbz",
+        ],
+    ),
+    ("7z", &["compressed"], 0, "37 7A BC AF 27 1C", &["7z"]),
+    (
+        "RAR",
+        &["compressed"],
+        0,
+        "52 61 72 21 1A 07",
+        &["rar", "cbr"],
+    ),
+    ("gzip", &["compressed"], 0, "1F 8B", &["gz", "tgz"]),
+    ("bzip2", &["compressed"], 0, "42 5A 68", &["bz2", "tbz2"]),
+    (
+        "xz",
+        &["compressed"],
+        0,
+        "FD 37 7A 58 5A 00",
+        &["xz", "txz"],
+    ),
+    ("tar", &["compressed"], 257, "75 73 74 61 72", &["tar"]),
+    (
+        "ELF",
+        &["executable"],
+        0,
+        "7F 45 4C 46",
+        &["so", "o", "ko", "elf", "bin"],
+    ),
+    (
+        "PE",
+        &["executable"],
+        0,
+        "4D 5A",
+        &["exe", "dll", "sys", "scr", "efi", "com"],
+    ),
+    (
+        "Matroska",
+        &["video"],
+        0,
+        "1A 45 DF A3",
+        &["mkv", "webm", "mka", "mk3d"],
+    ),
+    // the same container holds photos and sound too, told apart by the brand after `ftyp`
+    (
+        "HEIF",
+        &["image"],
+        4,
+        "66 74 79 70 68 65 69 63",
+        &["heic", "heif"],
+    ),
+    (
+        "HEIF",
+        &["image"],
+        4,
+        "66 74 79 70 68 65 69 78",
+        &["heic", "heif"],
+    ),
+    (
+        "HEIF",
+        &["image"],
+        4,
+        "66 74 79 70 6D 69 66 31",
+        &["heic", "heif", "avif"],
+    ),
+    ("AVIF", &["image"], 4, "66 74 79 70 61 76 69 66", &["avif"]),
+    ("AVIF", &["image"], 4, "66 74 79 70 61 76 69 73", &["avif"]),
+    (
+        "M4A",
+        &["audio"],
+        4,
+        "66 74 79 70 4D 34 41 20",
+        &["m4a", "m4b", "mp4"],
+    ),
+    (
+        "M4A",
+        &["audio"],
+        4,
+        "66 74 79 70 4D 34 42 20",
+        &["m4a", "m4b", "mp4"],
+    ),
+    (
+        "MP4",
+        &["video"],
+        4,
+        "66 74 79 70",
+        &["mp4", "m4v", "mov", "3gp"],
+    ),
+    (
+        "AVI",
+        &["video"],
+        0,
+        "52 49 46 46 ?? ?? ?? ?? 41 56 49 20",
+        &["avi"],
+    ),
+    (
+        "WAV",
+        &["audio"],
+        0,
+        "52 49 46 46 ?? ?? ?? ?? 57 41 56 45",
+        &["wav"],
+    ),
+    ("FLAC", &["audio"], 0, "66 4C 61 43", &["flac"]),
+    (
+        "Ogg",
+        &["audio"],
+        0,
+        "4F 67 67 53",
+        &["ogg", "oga", "ogv", "opus"],
+    ),
+    ("MP3", &["audio"], 0, "49 44 33", &["mp3"]),
+    (
+        "SQLite",
+        &["database"],
+        0,
+        "53 51 4C 69 74 65 20 66 6F 72 6D 61 74 20 33 00",
+        &["db", "sqlite", "sqlite3"],
+    ),
+];
+
+/// A magic number telling what a file is
+#[derive(Clone, Debug)]
+pub struct Signature {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub offset: usize,
+    /// `None` matches any byte
+    pub magic: Vec<Option<u8>>,
+    /// The extensions the format goes by, the signature takes over from any other
+    pub extensions: Vec<String>,
+}
+
+impl Signature {
+    pub fn matches(&self, header: &[u8]) -> bool {
+        header
+            .get(self.offset..self.offset + self.magic.len())
+            .is_some_and(|bytes| {
+                bytes
+                    .iter()
+                    .zip(self.magic.iter())
+                    .all(|(byte, magic)| magic.is_none_or(|magic| magic == *byte))
+            })
+    }
+}
+
+/// Parse the magic of a signature, hex bytes separated by spaces with `??` for any byte
+pub fn parse_magic(magic: &str) -> Result<Vec<Option<u8>>, String> {
+    let bytes = magic
+        .split_whitespace()
+        .map(|byte| match byte {
+            "??" => Ok(None),
+            _ => u8::from_str_radix(byte, 16)
+                .map(Some)
+                .map_err(|_| format!("{:?} is not a hex byte", byte)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if bytes.is_empty() {
+        return Err("The magic is empty".to_owned());
+    }
+    Ok(bytes)
+}
+
+pub fn builtin_signatures() -> Vec<Signature> {
+    BUILTIN_SIGNATURES
+        .iter()
+        .map(|(name, tags, offset, magic, extensions)| Signature {
+            name: name.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            offset: *offset,
+            magic: parse_magic(magic).unwrap(),
+            extensions: extensions.iter().map(|ext| ext.to_string()).collect(),
+        })
+        .collect()
+}
+
+/// The first bytes of a file
+pub fn read_header(path: &Path) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    fs::File::open(path)?
+        .take(HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+    Ok(header)
+}
+
+pub fn sniff<'a>(signatures: &'a [Signature], header: &[u8]) -> Option<&'a Signature> {
+    signatures
+        .iter()
+        .find(|signature| signature.matches(header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The name of the built-in signature a header matches
+    fn name_of(header: &[u8]) -> Option<String> {
+        sniff(&builtin_signatures(), header).map(|signature| signature.name.clone())
+    }
+
+    /// The header of an ISO media file with a brand
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        [&[0, 0, 0, 0x18][..], b"ftyp", brand, &[0, 0, 0, 0]].concat()
+    }
+
+    #[test]
+    fn magic_at_the_start() {
+        assert_eq!(
+            name_of(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").as_deref(),
+            Some("PNG")
+        );
+        assert_eq!(name_of(b"\xff\xd8\xff\xe0").as_deref(), Some("JPEG"));
+        assert_eq!(name_of(b"%PDF-1.7").as_deref(), Some("PDF"));
+        assert_eq!(name_of(b"PK\x03\x04").as_deref(), Some("ZIP"));
+        assert_eq!(name_of(b"plain text").as_deref(), None);
+        // too short to hold the magic
+        assert_eq!(name_of(b"\x89PN").as_deref(), None);
+        assert_eq!(name_of(b"").as_deref(), None);
+    }
+
+    #[test]
+    fn any_byte_and_offsets() {
+        assert_eq!(name_of(b"RIFF\x10\0\0\0WEBPVP8 ").as_deref(), Some("WebP"));
+        assert_eq!(name_of(b"RIFF\xff\xff\0\0AVI LIST").as_deref(), Some("AVI"));
+        assert_eq!(name_of(b"RIFF\x24\0\0\0WAVEfmt ").as_deref(), Some("WAV"));
+        assert_eq!(name_of(b"RIFF\x24\0\0\0ABCD").as_deref(), None);
+
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(name_of(&tar).as_deref(), Some("tar"));
+    }
+
+    #[test]
+    fn ftyp_brands() {
+        assert_eq!(name_of(&ftyp(b"heic")).as_deref(), Some("HEIF"));
+        assert_eq!(name_of(&ftyp(b"heix")).as_deref(), Some("HEIF"));
+        assert_eq!(name_of(&ftyp(b"mif1")).as_deref(), Some("HEIF"));
+        assert_eq!(name_of(&ftyp(b"avif")).as_deref(), Some("AVIF"));
+        assert_eq!(name_of(&ftyp(b"avis")).as_deref(), Some("AVIF"));
+        assert_eq!(name_of(&ftyp(b"M4A ")).as_deref(), Some("M4A"));
+        assert_eq!(name_of(&ftyp(b"M4B ")).as_deref(), Some("M4A"));
+        // any other brand is a video
+        assert_eq!(name_of(&ftyp(b"isom")).as_deref(), Some("MP4"));
+        assert_eq!(name_of(&ftyp(b"qt  ")).as_deref(), Some("MP4"));
+    }
+
+    #[test]
+    fn magic_in_hex() {
+        assert_eq!(
+            parse_magic("4d 5A ?? 00"),
+            Ok(vec![Some(0x4D), Some(0x5A), None, Some(0)])
+        );
+        assert!(parse_magic("").is_err());
+        assert!(parse_magic("4G").is_err());
+        assert!(parse_magic("4D5A").is_err());
+    }
+}
@@ -0,0 +1,454 @@
+use crate::datatype::{EntryKind, EntryMetadata, FileMetaCompat};
+use crate::query::Query;
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use mongodb::bson::{doc, Document};
+use mongodb::options::{ClientOptions, ReplaceOptions};
+use mongodb::Client;
+use rusqlite::types::{Type, Value};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use std::error::Error;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::{Arc, Mutex};
+
+/// The name of the collection, or the table, the entries are kept in
+pub(crate) const COLLECTION_NAME: &str = "tags";
+
+pub type StoreError = Box<dyn Error + Send + Sync>;
+
+/// Where the tagged entries are saved, and read back when sweeping
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Save an entry, replacing the one saved before for the same path
+    async fn save(&self, entry: FileMetaCompat) -> Result<(), StoreError>;
+
+    async fn find(&self, path: &Path) -> Result<Option<FileMetaCompat>, StoreError>;
+
+    /// The paths of the entries in a directory, and the directory itself
+    async fn paths_under(&self, root: &Path) -> Result<Vec<PathBuf>, StoreError>;
+
+    async fn remove(&self, path: &Path) -> Result<(), StoreError>;
+
+    /// The entries matching a query, which has no age tags as they are not saved
+    async fn find_matching(
+        &self,
+        query: &Query,
+    ) -> Result<BoxStream<'static, Result<FileMetaCompat, StoreError>>, StoreError>;
+}
+
+fn path_str(path: &Path) -> Result<&str, StoreError> {
+    path.to_str()
+        .ok_or_else(|| format!("Path {:?} is not valid UTF-8", path).into())
+}
+
+/// What the paths inside a directory start with
+fn dir_prefix(root: &str) -> String {
+    if root.ends_with(MAIN_SEPARATOR) {
+        root.to_owned()
+    } else {
+        format!("{}{}", root, MAIN_SEPARATOR)
+    }
+}
+
+/// Open the store given by an URL, `mongodb://...` or `sqlite:///path/to/index.db`
+pub async fn open(url: &str) -> Result<Box<dyn Store>, StoreError> {
+    if let Some(path) = url.strip_prefix("sqlite://") {
+        Ok(Box::new(SqliteStore::open(PathBuf::from(path))?))
+    } else if url.starts_with("mongodb://") || url.starts_with("mongodb+srv://") {
+        Ok(Box::new(MongoStore::open(url).await?))
+    } else {
+        Err(format!("Unknown store {:?}, expected mongodb:// or sqlite://", url).into())
+    }
+}
+
+pub struct MongoStore {
+    database: mongodb::Database,
+}
+
+impl MongoStore {
+    pub async fn open(url: &str) -> Result<Self, StoreError> {
+        let options = ClientOptions::parse(url).await?;
+        let client = Client::with_options(options)?;
+        Ok(MongoStore {
+            database: client.database("maidsweep"),
+        })
+    }
+
+    fn filter_of(query: &Query) -> Document {
+        let filters = |queries: &[Query]| queries.iter().map(Self::filter_of).collect::<Vec<_>>();
+        match query {
+            Query::Tag(tag) => doc! {"tags": tag},
+            Query::Bound(bound) => doc! {
+                bound.field.name(): {bound.comparison.mongo_operator(): bound.value as i64}
+            },
+            Query::Not(query) => doc! {"$nor": [Self::filter_of(query)]},
+            Query::And(queries) if queries.is_empty() => doc! {},
+            Query::And(queries) => doc! {"$and": filters(queries)},
+            Query::Or(queries) if queries.is_empty() => doc! {"$expr": false},
+            Query::Or(queries) => doc! {"$or": filters(queries)},
+        }
+    }
+
+    fn collection(&self) -> mongodb::Collection<FileMetaCompat> {
+        self.database.collection(COLLECTION_NAME)
+    }
+}
+
+#[async_trait]
+impl Store for MongoStore {
+    async fn save(&self, entry: FileMetaCompat) -> Result<(), StoreError> {
+        let filter = doc! {"path": path_str(&entry.path)?};
+        self.collection()
+            .replace_one(
+                filter,
+                &entry,
+                ReplaceOptions::builder().upsert(true).build(),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn find(&self, path: &Path) -> Result<Option<FileMetaCompat>, StoreError> {
+        Ok(self
+            .collection()
+            .find_one(doc! {"path": path_str(path)?}, None)
+            .await?)
+    }
+
+    async fn paths_under(&self, root: &Path) -> Result<Vec<PathBuf>, StoreError> {
+        let root = path_str(root)?;
+        let pattern = format!("^{}", regex::escape(&dir_prefix(root)));
+        let cursor = self
+            .collection()
+            .find(
+                doc! {"$or": [{"path": root}, {"path": {"$regex": pattern}}]},
+                None,
+            )
+            .await?;
+        Ok(cursor
+            .map_ok(|entry| entry.path)
+            .try_collect::<Vec<_>>()
+            .await?)
+    }
+
+    async fn remove(&self, path: &Path) -> Result<(), StoreError> {
+        self.collection()
+            .delete_many(doc! {"path": path_str(path)?}, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_matching(
+        &self,
+        query: &Query,
+    ) -> Result<BoxStream<'static, Result<FileMetaCompat, StoreError>>, StoreError> {
+        let cursor = self.collection().find(Self::filter_of(query), None).await?;
+        Ok(cursor.map_err(StoreError::from).boxed())
+    }
+}
+
+/// The columns of an entry, in the order `SqliteStore::read_entry` reads them
+const COLUMNS: &str =
+    "id, path, kind, size, last_modified, last_accessed, last_changed, inode, device, hash, members";
+
+/// The columns added after the table was first made, with their types
+const ADDED_COLUMNS: [(&str, &str); 2] = [("hash", "TEXT"), ("members", "TEXT")];
+
+/// How many entries are read from SQLite at once when searching
+const PAGE_SIZE: usize = 256;
+
+/// A local index in an SQLite database, so no server is needed
+#[derive(Clone)]
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    pub fn open(path: PathBuf) -> Result<Self, StoreError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let connection = Connection::open(&path)?;
+        connection.execute_batch(&format!(
+            "PRAGMA foreign_keys = ON;
+            CREATE TABLE IF NOT EXISTS {table} (
+                id INTEGER PRIMARY KEY,
+                path TEXT NOT NULL UNIQUE,
+                kind TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                last_modified INTEGER NOT NULL,
+                last_accessed INTEGER NOT NULL,
+                last_changed INTEGER NOT NULL,
+                inode INTEGER NOT NULL,
+                device INTEGER NOT NULL,
+                hash TEXT,
+                -- a JSON list of the paths, for group entries
+                members TEXT
+            );
+            CREATE TABLE IF NOT EXISTS {table}_tags (
+                entry INTEGER NOT NULL REFERENCES {table}(id) ON DELETE CASCADE,
+                tag TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS {table}_tags_tag ON {table}_tags(tag);",
+            table = COLLECTION_NAME
+        ))?;
+        // indexes made by older versions lack some columns
+        for (column, column_type) in ADDED_COLUMNS {
+            let exists: bool = connection.query_row(
+                &format!(
+                    "SELECT COUNT(*) > 0 FROM pragma_table_info('{}') WHERE name = ?1",
+                    COLLECTION_NAME
+                ),
+                params![column],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                connection.execute(
+                    &format!(
+                        "ALTER TABLE {} ADD COLUMN {} {}",
+                        COLLECTION_NAME, column, column_type
+                    ),
+                    [],
+                )?;
+            }
+        }
+        Ok(SqliteStore {
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    /// Read a row of the `COLUMNS` along with its tags
+    fn read_entry(connection: &Connection, row: &Row) -> rusqlite::Result<FileMetaCompat> {
+        let id: i64 = row.get(0)?;
+        let path: String = row.get(1)?;
+        let kind: String = row.get(2)?;
+        // the numbers are stored as signed, casting back restores them
+        let number = |index| row.get::<_, i64>(index).map(|number| number as u64);
+        let metadata = EntryMetadata {
+            kind: EntryKind::from_name(&kind).unwrap_or_default(),
+            size: number(3)?,
+            last_modified: number(4)?,
+            last_accessed: number(5)?,
+            last_changed: number(6)?,
+            inode: number(7)?,
+            device: number(8)?,
+            hash: row.get(9)?,
+        };
+        let members = match row.get::<_, Option<String>>(10)? {
+            Some(members) => serde_json::from_str(&members).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(10, Type::Text, Box::new(e))
+            })?,
+            None => vec![],
+        };
+        let tags = connection
+            .prepare_cached(&format!(
+                "SELECT tag FROM {}_tags WHERE entry = ?1",
+                COLLECTION_NAME
+            ))?
+            .query_map(params![id], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(FileMetaCompat {
+            path: PathBuf::from(path),
+            tags,
+            metadata,
+            members,
+        })
+    }
+
+    /// The `WHERE` clause of a query, with the values for its parameters
+    fn condition_of(query: &Query, values: &mut Vec<Value>) -> String {
+        let mut join = |queries: &[Query], operator: &str| {
+            let conditions: Vec<String> = queries
+                .iter()
+                .map(|query| format!("({})", Self::condition_of(query, values)))
+                .collect();
+            conditions.join(operator)
+        };
+        match query {
+            Query::Tag(tag) => {
+                values.push(Value::from(tag.clone()));
+                format!(
+                    "id IN (SELECT entry FROM {}_tags WHERE tag = ?)",
+                    COLLECTION_NAME
+                )
+            }
+            Query::Bound(bound) => {
+                values.push(Value::from(bound.value as i64));
+                format!(
+                    "{} {} ?",
+                    bound.field.name(),
+                    bound.comparison.sql_operator()
+                )
+            }
+            Query::Not(query) => format!("NOT ({})", Self::condition_of(query, values)),
+            Query::And(queries) if queries.is_empty() => "1".to_owned(),
+            Query::And(queries) => join(queries, " AND "),
+            Query::Or(queries) if queries.is_empty() => "0".to_owned(),
+            Query::Or(queries) => join(queries, " OR "),
+        }
+    }
+
+    /// Run a query on a blocking thread, as SQLite calls block
+    async fn with_connection<F, T>(&self, f: F) -> Result<T, StoreError>
+    where
+        F: FnOnce(&mut Connection) -> Result<T, StoreError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || f(&mut connection.lock().unwrap())).await?
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn save(&self, entry: FileMetaCompat) -> Result<(), StoreError> {
+        self.with_connection(move |connection| {
+            let path = path_str(&entry.path)?;
+            let metadata = &entry.metadata;
+            let members = if entry.members.is_empty() {
+                None
+            } else {
+                Some(serde_json::to_string(&entry.members)?)
+            };
+            let transaction = connection.transaction()?;
+            let id: i64 = transaction.query_row(
+                &format!(
+                    "INSERT INTO {} (
+                        path, kind, size, last_modified, last_accessed, last_changed, inode, device,
+                        hash, members
+                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                    ON CONFLICT (path) DO UPDATE SET
+                        kind = excluded.kind,
+                        size = excluded.size,
+                        last_modified = excluded.last_modified,
+                        last_accessed = excluded.last_accessed,
+                        last_changed = excluded.last_changed,
+                        inode = excluded.inode,
+                        device = excluded.device,
+                        hash = excluded.hash,
+                        members = excluded.members
+                    RETURNING id",
+                    COLLECTION_NAME
+                ),
+                params![
+                    path,
+                    metadata.kind.name(),
+                    metadata.size as i64,
+                    metadata.last_modified as i64,
+                    metadata.last_accessed as i64,
+                    metadata.last_changed as i64,
+                    metadata.inode as i64,
+                    metadata.device as i64,
+                    metadata.hash,
+                    members
+                ],
+                |row| row.get(0),
+            )?;
+            transaction.execute(
+                &format!("DELETE FROM {}_tags WHERE entry = ?1", COLLECTION_NAME),
+                params![id],
+            )?;
+            for tag in entry.tags.iter() {
+                transaction.execute(
+                    &format!(
+                        "INSERT INTO {}_tags (entry, tag) VALUES (?1, ?2)",
+                        COLLECTION_NAME
+                    ),
+                    params![id, tag],
+                )?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find(&self, path: &Path) -> Result<Option<FileMetaCompat>, StoreError> {
+        let path = path_str(path)?.to_owned();
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM {} WHERE path = ?1",
+                COLUMNS, COLLECTION_NAME
+            ))?;
+            let entry = statement
+                .query_row(params![path], |row| Self::read_entry(connection, row))
+                .optional()?;
+            Ok(entry)
+        })
+        .await
+    }
+
+    async fn paths_under(&self, root: &Path) -> Result<Vec<PathBuf>, StoreError> {
+        let root = path_str(root)?.to_owned();
+        self.with_connection(move |connection| {
+            let prefix = dir_prefix(&root);
+            let mut statement = connection.prepare(&format!(
+                "SELECT path FROM {}
+                WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+                COLLECTION_NAME
+            ))?;
+            let paths = statement
+                .query_map(params![root, prefix], |row| row.get::<_, String>(0))?
+                .map(|path| path.map(PathBuf::from))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(paths)
+        })
+        .await
+    }
+
+    async fn remove(&self, path: &Path) -> Result<(), StoreError> {
+        let path = path_str(path)?.to_owned();
+        self.with_connection(move |connection| {
+            connection.execute(
+                &format!("DELETE FROM {} WHERE path = ?1", COLLECTION_NAME),
+                params![path],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_matching(
+        &self,
+        query: &Query,
+    ) -> Result<BoxStream<'static, Result<FileMetaCompat, StoreError>>, StoreError> {
+        let mut values = vec![];
+        let condition = Self::condition_of(query, &mut values);
+        let store = self.clone();
+        // a page at a time in the order of the ids, so the rows are not all kept in memory
+        // and the connection is free for others while they are handled
+        let pages = stream::try_unfold(Some(0), move |after| {
+            let (store, condition, mut values) = (store.clone(), condition.clone(), values.clone());
+            async move {
+                let after: i64 = match after {
+                    Some(after) => after,
+                    None => return Ok::<_, StoreError>(None),
+                };
+                values.push(Value::from(after));
+                let page = store
+                    .with_connection(move |connection| {
+                        let mut statement = connection.prepare(&format!(
+                            "SELECT {} FROM {} WHERE ({}) AND id > ? ORDER BY id LIMIT {}",
+                            COLUMNS, COLLECTION_NAME, condition, PAGE_SIZE
+                        ))?;
+                        let page = statement
+                            .query_map(params_from_iter(values), |row| {
+                                Ok((row.get::<_, i64>(0)?, Self::read_entry(connection, row)?))
+                            })?
+                            .collect::<Result<Vec<_>, _>>()?;
+                        Ok(page)
+                    })
+                    .await?;
+                let next = match page.last() {
+                    Some((id, _)) if page.len() == PAGE_SIZE => Some(*id),
+                    _ => None,
+                };
+                let entries = page
+                    .into_iter()
+                    .map(|(_, entry)| Ok::<_, StoreError>(entry));
+                Ok(Some((stream::iter(entries), next)))
+            }
+        });
+        Ok(pages.try_flatten().boxed())
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// How a taken destination was dealt with
+#[derive(Debug)]
+pub enum Resolution {
+    Skipped,
+    Overwritten,
+    Identical,
+    Renamed(PathBuf),
+}
+
+impl Display for Resolution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Resolution::Skipped => write!(f, "skipped"),
+            Resolution::Overwritten => write!(f, "overwritten"),
+            Resolution::Identical => write!(f, "skipped, identical"),
+            Resolution::Renamed(path) => write!(f, "renamed to {:?}", path),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Conflict {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub resolution: Resolution,
+}
+
+/// What happened during a run, printed when it ends
+#[derive(Default)]
+pub struct RunSummary {
+    conflicts: Mutex<Vec<Conflict>>,
+}
+
+impl RunSummary {
+    pub fn add_conflict(&self, conflict: Conflict) {
+        self.conflicts.lock().unwrap().push(conflict);
+    }
+
+    pub fn print(&self) {
+        let conflicts = self.conflicts.lock().unwrap();
+        if conflicts.is_empty() {
+            return;
+        }
+        println!("{} conflicts:", conflicts.len());
+        for conflict in conflicts.iter() {
+            println!(
+                "  {:?} -> {:?}: {}",
+                conflict.source, conflict.destination, conflict.resolution
+            );
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::{Local, TimeZone};
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// What a placeholder stands for
+#[derive(Clone, Debug, PartialEq)]
+pub enum Placeholder {
+    /// `{}`, the path
+    Path,
+    /// `{.}`, the path without extension
+    PathNoExt,
+    /// `{/}`, the basename
+    Basename,
+    /// `{/.}`, the basename without extension
+    BasenameNoExt,
+    /// `{//}`, the parent directory
+    Dirname,
+    /// `{0}`, all the tags as `#tag1#tag2`
+    Tags,
+    /// `{1}`, `{2}`, ..., the n-th tag
+    Tag(usize),
+    /// `{year}`, the year it was last modified
+    Year,
+    /// `{month}`, the month it was last modified
+    Month,
+    /// `{ext}`, the extension
+    Extension,
+    /// `{root}`, the name of the scanned directory it is found in
+    Root,
+}
+
+impl Placeholder {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "" => Placeholder::Path,
+            "." => Placeholder::PathNoExt,
+            "/" => Placeholder::Basename,
+            "/." => Placeholder::BasenameNoExt,
+            "//" => Placeholder::Dirname,
+            "0" => Placeholder::Tags,
+            "year" => Placeholder::Year,
+            "month" => Placeholder::Month,
+            "ext" => Placeholder::Extension,
+            "root" => Placeholder::Root,
+            _ => match name.parse::<usize>() {
+                Ok(index) => Placeholder::Tag(index),
+                Err(_) => return None,
+            },
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Text(OsString),
+    Placeholder(Placeholder),
+}
+
+/// Turn bytes of an `OsStr` back into one, they must only be split or joined at ASCII characters
+pub fn os_string(bytes: Vec<u8>) -> OsString {
+    // SAFETY: what is not ASCII is kept whole, as it came from an `OsStr`
+    unsafe { OsString::from_encoded_bytes_unchecked(bytes) }
+}
+
+/// What the placeholders are replaced with
+pub struct Values<'a> {
+    pub path: &'a Path,
+    pub tags: &'a [String],
+    /// The scanned directories, to find out `{root}`
+    pub roots: &'a [PathBuf],
+    /// When it was last modified, in seconds since the epoch, as read when it was tagged
+    pub modified: Option<u64>,
+    /// The file `{ext}` is taken from, the first one of a series or split archive
+    pub file: &'a Path,
+}
+
+impl Values<'_> {
+    fn modified(&self, format: &str) -> OsString {
+        self.modified
+            .and_then(|seconds| Local.timestamp_opt(seconds as i64, 0).single())
+            .map(|time| time.format(format).to_string())
+            .unwrap_or_default()
+            .into()
+    }
+
+    fn root(&self) -> OsString {
+        self.roots
+            .iter()
+            .find(|root| self.path.starts_with(root))
+            .and_then(|root| root.canonicalize().ok())
+            .and_then(|root| root.file_name().map(OsStr::to_owned))
+            .unwrap_or_default()
+    }
+
+    fn get(&self, placeholder: &Placeholder) -> OsString {
+        let owned = |s: Option<&OsStr>| s.unwrap_or_default().to_owned();
+        match placeholder {
+            Placeholder::Path => self.path.as_os_str().to_owned(),
+            Placeholder::PathNoExt => self.path.with_extension("").into_os_string(),
+            Placeholder::Basename => owned(self.path.file_name()),
+            Placeholder::BasenameNoExt => owned(self.path.file_stem()),
+            Placeholder::Dirname => owned(self.path.parent().map(Path::as_os_str)),
+            Placeholder::Tags => format!("#{}", self.tags.join("#")).into(),
+            Placeholder::Tag(index) => index
+                .checked_sub(1)
+                .and_then(|index| self.tags.get(index))
+                .cloned()
+                .unwrap_or_default()
+                .into(),
+            Placeholder::Year => self.modified("%Y"),
+            Placeholder::Month => self.modified("%m"),
+            Placeholder::Extension => owned(self.file.extension()),
+            Placeholder::Root => self.root(),
+        }
+    }
+}
+
+/// A string with placeholders like `{}` or `{1}` in it.
+/// Braces that do not form a known placeholder are kept as they are.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    tokens: Vec<Token>,
+}
+
+impl Template {
+    pub fn parse(template: impl AsRef<OsStr>) -> Self {
+        let mut tokens = vec![];
+        let mut text = vec![];
+        let mut rest = template.as_ref().as_encoded_bytes();
+        while let Some(start) = rest.iter().position(|&byte| byte == b'{') {
+            text.extend_from_slice(&rest[..start]);
+            let placeholder = rest[start + 1..]
+                .iter()
+                .position(|&byte| byte == b'}')
+                .and_then(|end| {
+                    std::str::from_utf8(&rest[start + 1..start + 1 + end])
+                        .ok()
+                        .and_then(Placeholder::from_name)
+                        .map(|placeholder| (placeholder, start + end + 2))
+                });
+            match placeholder {
+                Some((placeholder, next)) => {
+                    if !text.is_empty() {
+                        tokens.push(Token::Text(os_string(std::mem::take(&mut text))));
+                    }
+                    tokens.push(Token::Placeholder(placeholder));
+                    rest = &rest[next..];
+                }
+                None => {
+                    text.push(b'{');
+                    rest = &rest[start + 1..];
+                }
+            }
+        }
+        text.extend_from_slice(rest);
+        if !text.is_empty() {
+            tokens.push(Token::Text(os_string(text)));
+        }
+        Template { tokens }
+    }
+
+    /// For clap to parse arguments into templates
+    pub fn parse_arg(template: &str) -> Result<Self, String> {
+        Ok(Self::parse(template))
+    }
+
+    pub fn placeholders(&self) -> impl Iterator<Item = &Placeholder> {
+        self.tokens.iter().filter_map(|token| match token {
+            Token::Placeholder(placeholder) => Some(placeholder),
+            Token::Text(_) => None,
+        })
+    }
+
+    /// Replace the placeholders in one pass, so replaced text is never looked at again
+    pub fn expand(&self, values: &Values) -> OsString {
+        let mut expanded = OsString::new();
+        for token in self.tokens.iter() {
+            match token {
+                Token::Text(text) => expanded.push(text),
+                Token::Placeholder(placeholder) => expanded.push(values.get(placeholder)),
+            }
+        }
+        expanded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(template: &str, path: &str, file: &str) -> OsString {
+        let tags = ["image".to_owned(), "recent".to_owned()];
+        Template::parse(template).expand(&Values {
+            path: Path::new(path),
+            tags: &tags,
+            roots: &[],
+            // the middle of June 2021, in any time zone
+            modified: Some(1_623_758_400),
+            file: Path::new(file),
+        })
+    }
+
+    #[test]
+    fn paths_and_tags() {
+        let path = "/photos/trip/beach.jpg";
+        assert_eq!(expand("{}", path, path), "/photos/trip/beach.jpg");
+        assert_eq!(expand("{.}", path, path), "/photos/trip/beach");
+        assert_eq!(expand("{/}", path, path), "beach.jpg");
+        assert_eq!(expand("{/.}", path, path), "beach");
+        assert_eq!(expand("{//}", path, path), "/photos/trip");
+        assert_eq!(expand("{0}", path, path), "#image#recent");
+        assert_eq!(expand("{1}/{2}/{3}", path, path), "image/recent/");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_text() {
+        let path = "/a/b.txt";
+        assert_eq!(expand("{foo}/{1}", path, path), "{foo}/image");
+        assert_eq!(expand("{{}}", path, path), "{/a/b.txt}");
+        assert_eq!(expand("{ 1}{-1}", path, path), "{ 1}{-1}");
+        assert_eq!(expand("{", path, path), "{");
+        assert_eq!(expand("}{", path, path), "}{");
+        assert_eq!(Template::parse("{foo}").placeholders().count(), 0);
+    }
+
+    #[test]
+    fn replaced_text_is_not_expanded_again() {
+        assert_eq!(expand("{/}", "/a/{1}.txt", "/a/{1}.txt"), "{1}.txt");
+    }
+
+    #[test]
+    fn date_and_extension_of_the_entry() {
+        // a series is laid out by its first file
+        assert_eq!(
+            expand(
+                "{1}/{year}/{month}/{ext}",
+                "/scans/page",
+                "/scans/page_01.png"
+            ),
+            "image/2021/06/png"
+        );
+        let tags = [];
+        let undated = Template::parse("{year}{month}{ext}").expand(&Values {
+            path: Path::new("/a/Makefile"),
+            tags: &tags,
+            roots: &[],
+            modified: None,
+            file: Path::new("/a/Makefile"),
+        });
+        assert_eq!(undated, "");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn text_that_is_not_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let template = OsStr::from_bytes(b"\xff{/}\xfe");
+        let path = Path::new(OsStr::from_bytes(b"/a/\xfd.txt"));
+        let tags = [];
+        let expanded = Template::parse(template).expand(&Values {
+            path,
+            tags: &tags,
+            roots: &[],
+            modified: None,
+            file: path,
+        });
+        assert_eq!(expanded.as_bytes(), b"\xff\xfd.txt\xfe");
+    }
+
+    #[test]
+    fn root_is_the_scanned_directory() {
+        let root = std::env::temp_dir();
+        let name = root.canonicalize().unwrap().file_name().unwrap().to_owned();
+        let path = root.join("a").join("b.txt");
+        let tags = [];
+        let roots = [PathBuf::from("/nowhere"), root];
+        let values = Values {
+            path: &path,
+            tags: &tags,
+            roots: &roots,
+            modified: None,
+            file: &path,
+        };
+        assert_eq!(Template::parse("{root}").expand(&values), name);
+    }
+}
@@ -0,0 +1,293 @@
+use crate::fileop::{self, FileOpError};
+use chrono::Local;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const INFO_EXTENSION: &str = "trashinfo";
+
+/// An entry in the trash can
+#[derive(Debug)]
+pub struct TrashEntry {
+    /// The name in the `files` directory
+    pub name: String,
+    pub original_path: PathBuf,
+    pub deletion_date: String,
+}
+
+/// The home trash as in the freedesktop.org Trash specification
+pub fn trash_dir() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("Trash")
+}
+
+fn files_dir() -> PathBuf {
+    trash_dir().join("files")
+}
+
+fn info_dir() -> PathBuf {
+    trash_dir().join("info")
+}
+
+fn info_path(name: &str) -> PathBuf {
+    info_dir().join(format!("{}.{}", name, INFO_EXTENSION))
+}
+
+/// Percent-encode a path for the `Path` key, keeping the separators
+fn encode_path(path: &Path) -> String {
+    path.to_string_lossy()
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn decode_path(encoded: &str) -> PathBuf {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = if byte == b'%' && tail.len() >= 2 {
+            std::str::from_utf8(&tail[..2])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Reserve a name in the trash by creating its info file
+fn reserve(original_path: &Path) -> Result<String, FileOpError> {
+    let info_dir = info_dir();
+    fs::create_dir_all(&info_dir).map_err(|source| FileOpError {
+        operation: "create directory",
+        path: info_dir.clone(),
+        source,
+    })?;
+
+    let base_name = original_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "unnamed".to_owned());
+    let info = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        encode_path(original_path),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+
+    for suffix in 1.. {
+        let name = if suffix == 1 {
+            base_name.clone()
+        } else {
+            format!("{}.{}", base_name, suffix)
+        };
+        if files_dir().join(&name).exists() {
+            continue;
+        }
+        // creating the info file exclusively is what claims the name
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(info_path(&name))
+        {
+            Ok(mut file) => {
+                file.write_all(info.as_bytes())
+                    .map_err(|source| FileOpError {
+                        operation: "write",
+                        path: info_path(&name),
+                        source,
+                    })?;
+                return Ok(name);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(source) => {
+                return Err(FileOpError {
+                    operation: "create",
+                    path: info_path(&name),
+                    source,
+                })
+            }
+        }
+    }
+    unreachable!()
+}
+
+/// Move a file or a directory to the trash, returns where it is now
+pub fn trash(path: &Path) -> Result<PathBuf, FileOpError> {
+    let original_path = std::path::absolute(path).map_err(|source| FileOpError {
+        operation: "resolve",
+        path: path.to_owned(),
+        source,
+    })?;
+    let name = reserve(&original_path)?;
+
+    let files_dir = files_dir();
+    let trashed = files_dir.join(&name);
+    let result = fs::create_dir_all(&files_dir)
+        .map_err(|source| FileOpError {
+            operation: "create directory",
+            path: files_dir,
+            source,
+        })
+        .and_then(|_| fileop::move_path(&original_path, &trashed));
+    if let Err(e) = result {
+        let _ = fs::remove_file(info_path(&name));
+        return Err(e);
+    }
+    Ok(trashed)
+}
+
+/// Drop the info of an entry that has been moved out of the trash
+pub fn forget(trashed: &Path) -> io::Result<()> {
+    let name = trashed.file_name().unwrap_or_default().to_string_lossy();
+    fs::remove_file(info_path(&name))
+}
+
+fn parse_info(name: String, content: &str) -> Option<TrashEntry> {
+    let mut original_path = None;
+    let mut deletion_date = String::new();
+    for line in content.lines() {
+        if let Some(value) = line.strip_prefix("Path=") {
+            original_path = Some(decode_path(value));
+        } else if let Some(value) = line.strip_prefix("DeletionDate=") {
+            deletion_date = value.to_owned();
+        }
+    }
+    Some(TrashEntry {
+        name,
+        original_path: original_path?,
+        deletion_date,
+    })
+}
+
+pub fn list() -> io::Result<Vec<TrashEntry>> {
+    let entries = match fs::read_dir(info_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut trashed = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != INFO_EXTENSION) {
+            continue;
+        }
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        if let Some(entry) = parse_info(name.into_owned(), &fs::read_to_string(&path)?) {
+            trashed.push(entry);
+        }
+    }
+    trashed.sort_by(|a, b| a.deletion_date.cmp(&b.deletion_date));
+    Ok(trashed)
+}
+
+pub fn print_list() -> Result<(), Box<dyn Error>> {
+    for entry in list()? {
+        println!(
+            "{}\t{}\t{}",
+            entry.deletion_date,
+            entry.name,
+            entry.original_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Put entries back where they were, they can be given by their name in the trash or their original path
+pub fn restore(items: &[String]) -> Result<(), Box<dyn Error>> {
+    let entries = list()?;
+    for item in items {
+        let matched: Vec<&TrashEntry> = entries
+            .iter()
+            .filter(|entry| entry.name == *item || entry.original_path == Path::new(item))
+            .collect();
+        if matched.is_empty() {
+            eprintln!("Error: {} is not in the trash", item);
+            continue;
+        }
+
+        for entry in matched {
+            if entry.original_path.exists() {
+                eprintln!(
+                    "Error: Cannot restore {}, {:?} is occupied",
+                    entry.name, entry.original_path
+                );
+                continue;
+            }
+            if let Some(parent) = entry.original_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let trashed = files_dir().join(&entry.name);
+            match fileop::move_path(&trashed, &entry.original_path) {
+                Ok(()) => {
+                    forget(&trashed)?;
+                    println!("Restored {:?}", entry.original_path);
+                }
+                Err(e) => eprintln!("Error: {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Permanently delete everything in the trash
+pub fn empty() -> Result<(), Box<dyn Error>> {
+    let entries = list()?;
+    for entry in entries.iter() {
+        let trashed = files_dir().join(&entry.name);
+        if fs::symlink_metadata(&trashed).is_ok() {
+            fileop::remove_path(&trashed)?;
+        }
+        forget(&trashed)?;
+    }
+    println!("Removed {} entries from the trash", entries.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_percent_encoded() {
+        let path = Path::new("/home/me/My Files/100%_ré.txt");
+        let encoded = encode_path(path);
+        assert_eq!(encoded, "/home/me/My%20Files/100%25_r%C3%A9.txt");
+        assert_eq!(decode_path(&encoded), path);
+        // a stray percent sign is kept as it is
+        assert_eq!(decode_path("/a%2/b%"), Path::new("/a%2/b%"));
+    }
+
+    #[test]
+    fn info_files() {
+        let info = "[Trash Info]\nPath=/tmp/a%20b.txt\nDeletionDate=2024-05-01T10:00:00\n";
+        let entry = parse_info("a b.txt.2".to_owned(), info).unwrap();
+        assert_eq!(entry.name, "a b.txt.2");
+        assert_eq!(entry.original_path, Path::new("/tmp/a b.txt"));
+        assert_eq!(entry.deletion_date, "2024-05-01T10:00:00");
+        // it cannot be restored without the path
+        assert!(parse_info(
+            "x".to_owned(),
+            "[Trash Info]\nDeletionDate=2024-05-01T10:00:00\n"
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,65 @@
+use crate::datatype::MatchReason;
+use regex::{Regex, RegexSet};
+use std::path::PathBuf;
+
+/// Files telling what a directory is, so it is tagged as a whole and not walked into
+#[derive(Debug)]
+pub struct TypicalRule {
+    /// The tag of the rule in the old form, or what it is called in the list
+    pub name: String,
+    pub tags: Vec<String>,
+    /// Higher ones are tried first, the ones of the same priority in order
+    pub priority: i64,
+    /// Any of these makes the rule match
+    pub patterns: RegexSet,
+    /// Each of these has to match a file for the rule to match
+    pub requires: Vec<Regex>,
+}
+
+impl TypicalRule {
+    /// Why the rule matches the names in a directory, by the files it is matched by,
+    /// or why it does not. The names are expected to be sorted, so the first one is the marker.
+    pub fn check(&self, names: &[(String, PathBuf)]) -> Result<Vec<MatchReason>, String> {
+        let reason = |pattern: &str, marker: &PathBuf| MatchReason::TypicalFile {
+            rule: self.name.clone(),
+            pattern: pattern.to_owned(),
+            marker: marker.clone(),
+        };
+        let mut reasons = vec![];
+        if !self.patterns.is_empty() {
+            let (index, marker) = names
+                .iter()
+                .find_map(|(name, marker)| {
+                    let index = self.patterns.matches(name).into_iter().next()?;
+                    Some((index, marker))
+                })
+                .ok_or("no name matches its patterns")?;
+            reasons.push(reason(&self.patterns.patterns()[index], marker));
+        }
+        for required in self.requires.iter() {
+            let (_, marker) = names
+                .iter()
+                .find(|(name, _)| required.is_match(name))
+                .ok_or_else(|| format!("no name matches `{}`", required.as_str()))?;
+            reasons.push(reason(required.as_str(), marker));
+        }
+        Ok(reasons)
+    }
+}
+
+/// The first rule matching the names in a directory, with why it matches.
+/// The rules are expected to be sorted by priority. The ones tried before are given
+/// to `missed` with why they do not match.
+pub fn find_typical<'a>(
+    rules: &'a [TypicalRule],
+    names: &[(String, PathBuf)],
+    mut missed: impl FnMut(&TypicalRule, String),
+) -> Option<(&'a TypicalRule, Vec<MatchReason>)> {
+    rules.iter().find_map(|rule| match rule.check(names) {
+        Ok(reasons) => Some((rule, reasons)),
+        Err(why) => {
+            missed(rule, why);
+            None
+        }
+    })
+}
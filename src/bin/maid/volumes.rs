@@ -0,0 +1,168 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Gives the name of the whole archive and the order of a volume from the match on its name
+type Volume = fn(&regex::Captures) -> (String, u32);
+
+/// The ways an archive is split, each with the name of the whole archive for a volume,
+/// and the order of the volume in the set. `.zip` and `.rar` are opened first in their sets,
+/// but they only make a set along with numbered volumes.
+static VOLUME_PATTERNS: Lazy<[(Regex, Volume); 4]> = Lazy::new(|| {
+    [
+        // movie.part1.rar, movie.part2.rar, or a self-extracting movie.part1.exe
+        (
+            Regex::new(r"(?i)^(?P<base>.+)\.part(?P<number>\d+)\.(rar|exe)$").unwrap(),
+            |captures| (format!("{}.rar", &captures["base"]), number(captures)),
+        ),
+        // movie.7z.001, movie.7z.002
+        (
+            Regex::new(r"(?i)^(?P<base>.+\.(7z|zip|rar|tar|gz|bz2|xz|tgz))\.(?P<number>\d{3})$")
+                .unwrap(),
+            |captures| (captures["base"].to_owned(), number(captures)),
+        ),
+        // movie.z01, movie.z02, movie.zip
+        (
+            Regex::new(r"(?i)^(?P<base>.+)\.(z(?P<number>\d{2})|zip)$").unwrap(),
+            |captures| (format!("{}.zip", &captures["base"]), main_first(captures)),
+        ),
+        // movie.r00, movie.r01, movie.rar
+        (
+            Regex::new(r"(?i)^(?P<base>.+)\.(r(?P<number>\d{2})|rar)$").unwrap(),
+            |captures| (format!("{}.rar", &captures["base"]), main_first(captures)),
+        ),
+    ]
+});
+
+fn number(captures: &regex::Captures) -> u32 {
+    captures["number"].parse().unwrap_or(u32::MAX)
+}
+
+/// The main volume comes first, then the numbered ones
+fn main_first(captures: &regex::Captures) -> u32 {
+    captures.name("number").map_or(0, |number| {
+        number.as_str().parse().unwrap_or(u32::MAX - 1) + 1
+    })
+}
+
+/// The volumes of one split archive
+#[derive(Debug)]
+pub struct VolumeSet {
+    /// The name of the whole archive, like `movie.rar`
+    pub name: String,
+    /// In the order they are read, the first one is opened to extract it
+    pub members: Vec<PathBuf>,
+}
+
+/// Find the split archives among the files of a directory, a set needs two volumes or more
+pub fn find_volume_sets(files: &[PathBuf]) -> Vec<VolumeSet> {
+    let mut sets: BTreeMap<(usize, String), Vec<(u32, PathBuf)>> = BTreeMap::new();
+    for file in files {
+        let name = match file.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        let found = VOLUME_PATTERNS
+            .iter()
+            .enumerate()
+            .find_map(|(index, (pattern, volume))| {
+                pattern
+                    .captures(name)
+                    .map(|captures| (index, volume(&captures)))
+            });
+        if let Some((index, (base, order))) = found {
+            sets.entry((index, base))
+                .or_default()
+                .push((order, file.clone()));
+        }
+    }
+
+    sets.into_iter()
+        // a lone zip or rar is not split
+        .filter(|(_, volumes)| volumes.len() > 1 && volumes.iter().any(|(order, _)| *order > 0))
+        .map(|((_, name), mut volumes)| {
+            volumes.sort();
+            VolumeSet {
+                name,
+                members: volumes.into_iter().map(|(_, path)| path).collect(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sets(names: &[&str]) -> Vec<(String, Vec<String>)> {
+        let files: Vec<PathBuf> = names
+            .iter()
+            .map(|name| PathBuf::from("/d").join(name))
+            .collect();
+        find_volume_sets(&files)
+            .into_iter()
+            .map(|set| {
+                let members = set
+                    .members
+                    .iter()
+                    .map(|member| member.file_name().unwrap().to_string_lossy().into_owned())
+                    .collect();
+                (set.name, members)
+            })
+            .collect()
+    }
+
+    fn set(name: &str, members: &[&str]) -> (String, Vec<String>) {
+        (
+            name.to_owned(),
+            members.iter().map(|member| member.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn volumes_in_order() {
+        assert_eq!(
+            sets(&[
+                "movie.part10.rar",
+                "movie.part2.rar",
+                "movie.part1.exe",
+                "notes.txt"
+            ]),
+            [set(
+                "movie.rar",
+                &["movie.part1.exe", "movie.part2.rar", "movie.part10.rar"]
+            )]
+        );
+        assert_eq!(
+            sets(&["backup.7z.002", "backup.7z.001"]),
+            [set("backup.7z", &["backup.7z.001", "backup.7z.002"])]
+        );
+    }
+
+    #[test]
+    fn the_main_volume_first() {
+        assert_eq!(
+            sets(&["photos.z02", "photos.zip", "photos.z01"]),
+            [set(
+                "photos.zip",
+                &["photos.zip", "photos.z01", "photos.z02"]
+            )]
+        );
+        assert_eq!(
+            sets(&["old.r00", "old.RAR", "old.r01"]),
+            [set("old.rar", &["old.RAR", "old.r00", "old.r01"])]
+        );
+    }
+
+    #[test]
+    fn one_volume_is_not_a_set() {
+        assert!(sets(&["movie.part1.rar"]).is_empty());
+        assert!(sets(&["photos.zip", "old.rar"]).is_empty());
+        // the same name split in two ways are two sets
+        assert_eq!(
+            sets(&["a.part1.rar", "a.part2.rar", "a.r00", "a.rar"]).len(),
+            2
+        );
+    }
+}